@@ -0,0 +1,112 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::windows_version::WindowsVersion;
+
+// API sets (api-ms-win-*.dll, ext-ms-*.dll) are virtual DLL names,
+// which the Windows loader maps to the DLL actually implementing them.
+
+pub enum ApiSetPolicy {
+	// Treat them as part of the OS.
+	Ignore,
+	// Replace them with their host DLL, which is then resolved like any other.
+	Map,
+}
+
+pub struct ApiSet {
+	// Matched against the start of the lowercased DLL name.
+	pub prefix: &'static str,
+	// None when the host DLL varies between versions or editions of Windows.
+	pub host: Option<&'static str>,
+	// The first version of Windows that knows of the API set.
+	pub since: WindowsVersion,
+}
+
+// More specific prefixes have to come first.
+const API_SETS: &[ApiSet] = &[
+	// The Universal CRT. Older versions of Windows need the redistributable,
+	// which installs both ucrtbase.dll and these forwarders.
+	ApiSet {
+		prefix: "api-ms-win-crt-",
+		host: Some("ucrtbase.dll"),
+		since: WindowsVersion::Win10,
+	},
+	ApiSet {
+		prefix: "api-ms-win-core-com-",
+		host: Some("combase.dll"),
+		since: WindowsVersion::Win8,
+	},
+	ApiSet {
+		prefix: "api-ms-win-core-winrt-",
+		host: Some("combase.dll"),
+		since: WindowsVersion::Win8,
+	},
+	ApiSet {
+		prefix: "api-ms-win-core-path-",
+		host: Some("kernelbase.dll"),
+		since: WindowsVersion::Win8,
+	},
+	ApiSet {
+		prefix: "api-ms-win-core-synch-l1-2-",
+		host: Some("kernelbase.dll"),
+		since: WindowsVersion::Win8,
+	},
+	ApiSet {
+		prefix: "api-ms-win-core-",
+		host: Some("kernelbase.dll"),
+		since: WindowsVersion::Win7,
+	},
+	ApiSet {
+		prefix: "api-ms-win-eventing-",
+		host: Some("advapi32.dll"),
+		since: WindowsVersion::Win7,
+	},
+	ApiSet {
+		prefix: "api-ms-win-security-",
+		host: Some("advapi32.dll"),
+		since: WindowsVersion::Win7,
+	},
+	ApiSet {
+		prefix: "api-ms-win-service-",
+		host: Some("advapi32.dll"),
+		since: WindowsVersion::Win7,
+	},
+	ApiSet {
+		prefix: "api-ms-win-shcore-",
+		host: Some("shcore.dll"),
+		since: WindowsVersion::Win81,
+	},
+	ApiSet {
+		prefix: "api-ms-win-",
+		host: None,
+		since: WindowsVersion::Win7,
+	},
+	// Extension API sets are optional, and may not be implemented at all on some editions.
+	ApiSet {
+		prefix: "ext-ms-",
+		host: None,
+		since: WindowsVersion::Win8,
+	},
+];
+
+// Look up the API set the DLL name belongs to, if any.
+pub fn find_api_set(name: &str) -> Option<&'static ApiSet> {
+	let name = name.to_ascii_lowercase();
+	if !name.ends_with(".dll") {
+		return None;
+	}
+	return API_SETS.iter().find(|set| name.starts_with(set.prefix));
+}
@@ -0,0 +1,147 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// Strings escaped for JSON are valid as DOT identifiers and labels as well.
+use crate::json::escape as quote;
+use crate::parser::{Dependency, DependencyKind, Object};
+use crate::resolver::{Library, Status};
+use crate::settings::Settings;
+
+const COLOUR_EXECUTABLE: &str = "lightblue";
+const COLOUR_RESOLVED: &str = "palegreen";
+const COLOUR_IGNORED: &str = "lightgrey";
+const COLOUR_FAILED: &str = "salmon";
+const COLOUR_UNREADABLE: &str = "orange";
+const COLOUR_LINKER_SCRIPT: &str = "khaki";
+const COLOUR_API_SET: &str = "lavender";
+
+fn format_size(path: &Path) -> String {
+	let size = match fs::metadata(path) {
+		Ok(meta) => meta.len(),
+		Err(_) => return String::from("size unknown"),
+	};
+
+	const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+	if size < 1024 {
+		return format!("{} B", size);
+	}
+	let mut value = size as f64 / 1024.0;
+	let mut unit = 0;
+	while value >= 1024.0 && unit < UNITS.len() - 1 {
+		value /= 1024.0;
+		unit += 1;
+	}
+	return format!("{:.1} {}", value, UNITS[unit]);
+}
+
+fn write_node(out: &mut String, name: &str, label: &str, colour: &str) {
+	let _ = writeln!(
+		out,
+		"\t{} [label={}, fillcolor={}];",
+		quote(name),
+		quote(label),
+		colour
+	);
+}
+
+fn write_edges(out: &mut String, from: &str, deps: &[Dependency]) {
+	for dep in deps.iter() {
+		let style = match dep.kind {
+			DependencyKind::Normal => "solid",
+			DependencyKind::DelayLoad => "dashed",
+			DependencyKind::Weak => "dotted",
+		};
+		let _ = writeln!(
+			out,
+			"\t{} -> {} [style={}];",
+			quote(from),
+			quote(&dep.name),
+			style
+		);
+	}
+}
+
+pub fn print_dot(executable: &Object, deps: &HashMap<String, Library>, settings: &Settings) {
+	let exe_name = settings.executable_name();
+
+	let mut out = String::new();
+	out.push_str("digraph copydeps {\n");
+	out.push_str("\trankdir=LR;\n");
+	out.push_str("\tnode [shape=box, style=filled];\n");
+
+	let exe_label = format!(
+		"{}\n{}\n{}",
+		exe_name,
+		settings.executable.to_string_lossy(),
+		format_size(&settings.executable)
+	);
+	write_node(&mut out, &exe_name, &exe_label, COLOUR_EXECUTABLE);
+
+	let mut sorted_keys = deps.keys().collect::<Vec<&String>>();
+	sorted_keys.sort();
+	for name in sorted_keys.iter() {
+		let library = deps.get(name.as_str()).unwrap();
+		match &library.status {
+			Status::Ignored(_) => write_node(
+				&mut out,
+				name,
+				&format!("{}\n(ignored)", name),
+				COLOUR_IGNORED,
+			),
+			Status::FailedToResolve => write_node(
+				&mut out,
+				name,
+				&format!("{}\n(failed to resolve)", name),
+				COLOUR_FAILED,
+			),
+			Status::Resolved(path, _) => {
+				let label = format!(
+					"{}\n{}\n{}",
+					name,
+					path.to_string_lossy(),
+					format_size(path)
+				);
+				write_node(&mut out, name, &label, COLOUR_RESOLVED);
+			}
+			Status::LinkerScript(path, _) => {
+				let label = format!("{}\n{}\n(linker script)", name, path.to_string_lossy());
+				write_node(&mut out, name, &label, COLOUR_LINKER_SCRIPT);
+			}
+			Status::ApiSet(host) => {
+				let label = format!("{}\n{}\n(API set)", name, host);
+				write_node(&mut out, name, &label, COLOUR_API_SET);
+			}
+			Status::Unreadable(path, _, _) => {
+				let label = format!("{}\n{}\n(unreadable)", name, path.to_string_lossy());
+				write_node(&mut out, name, &label, COLOUR_UNREADABLE);
+			}
+		}
+	}
+
+	write_edges(&mut out, &exe_name, &executable.deps);
+	for name in sorted_keys.iter() {
+		let library = deps.get(name.as_str()).unwrap();
+		write_edges(&mut out, name, &library.needs);
+	}
+
+	out.push_str("}\n");
+	print!("{}", out);
+}
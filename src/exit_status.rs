@@ -20,3 +20,4 @@ pub const EXIT_OPEN_EXE_FAILED: i32 = 2;
 pub const EXIT_OPEN_LIB_FAILED: i32 = 3;
 pub const EXIT_RESOLVE_FAILED: i32 = 4;
 pub const EXIT_COPY_FAILED: i32 = 5;
+pub const EXIT_UNREADABLE_LIB: i32 = 6;
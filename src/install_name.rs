@@ -0,0 +1,446 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	collections::HashMap,
+	fmt::{Display, Formatter},
+	fs,
+	path::{Path, PathBuf},
+};
+
+extern crate goblin;
+use goblin::mach::header::{SIZEOF_HEADER_32, SIZEOF_HEADER_64};
+use goblin::mach::load_command::{CommandVariant, LC_RPATH};
+use goblin::mach::{Mach, MachO};
+
+use crate::permissions::write_in_place;
+use crate::resolver::{leaf_name, Library, Status};
+
+// Both the dylib and the rpath load commands are followed by their string.
+const SIZEOF_DYLIB_COMMAND: usize = 24;
+const SIZEOF_RPATH_COMMAND: usize = 12;
+
+pub enum InstallNameError {
+	FailedToReadFile(PathBuf, std::io::Error),
+	FailedToParseFile(PathBuf, goblin::error::Error),
+	FailedToWriteFile(PathBuf, std::io::Error),
+	NotMachO(PathBuf),
+	NoRoom(PathBuf, usize, usize),
+}
+
+impl Display for InstallNameError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			InstallNameError::FailedToReadFile(path, err) => write!(
+				f,
+				"Failed to read file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			InstallNameError::FailedToParseFile(path, err) => write!(
+				f,
+				"Failed to parse file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			InstallNameError::FailedToWriteFile(path, err) => write!(
+				f,
+				"Failed to write file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			InstallNameError::NotMachO(path) => write!(
+				f,
+				"File \"{}\" is not a Mach-O object",
+				path.to_string_lossy()
+			),
+			InstallNameError::NoRoom(path, needed, available) => write!(
+				f,
+				"Cannot rewrite install names of \"{}\": the load commands would take {} bytes, \
+				but the header padding only leaves room for {} \
+				(relinking with -headerpad_max_install_names would help)",
+				path.to_string_lossy(),
+				needed,
+				available
+			),
+		}
+	}
+}
+
+// Changes to make to the load commands of a copied Mach-O file.
+pub struct Rewrite<'a> {
+	// New directory part of the install names, e.g. "@rpath" or "@executable_path/../lib".
+	pub prefix: &'a str,
+	// Install names used by the bundled libraries, and what to replace them with.
+	pub renames: &'a HashMap<String, String>,
+	// LC_RPATH entry to add, unless already present.
+	pub rpath: Option<String>,
+}
+
+// The new install names of all the libraries that go into the bundle.
+pub fn bundled_install_names(
+	deps: &HashMap<String, Library>,
+	prefix: &str,
+) -> HashMap<String, String> {
+	return deps
+		.iter()
+		.filter(|(_, library)| matches!(library.status, Status::Resolved(_, _)))
+		.map(|(name, _)| (name.clone(), format!("{}/{}", prefix, leaf_name(name))))
+		.collect();
+}
+
+enum Command {
+	// LC_ID_DYLIB is the only dylib command that does not refer to another library.
+	Dylib { name: String, is_id: bool },
+	Rpath(String),
+	Other,
+}
+
+struct LoadCommand {
+	offset: usize,
+	size: usize,
+	command: Command,
+}
+
+// The parts of a single-architecture Mach-O file needed to rewrite its load commands.
+struct Slice {
+	little_endian: bool,
+	is_64: bool,
+	header_size: usize,
+	commands: Vec<LoadCommand>,
+	// Where the load commands end, and how far they are allowed to grow.
+	commands_end: usize,
+	limit: usize,
+	// Has an LC_CODE_SIGNATURE, which covers the load commands.
+	signed: bool,
+}
+
+fn read_string(bytes: &[u8], start: usize, end: usize) -> String {
+	let tail = bytes.get(start..end).unwrap_or(&[]);
+	let len = tail.iter().position(|b| *b == 0).unwrap_or(tail.len());
+	return String::from_utf8_lossy(&tail[..len]).into_owned();
+}
+
+fn parse_slice(bytes: &[u8]) -> Result<Slice, goblin::error::Error> {
+	let macho = MachO::parse(bytes, 0)?;
+	let header_size = match macho.is_64 {
+		true => SIZEOF_HEADER_64,
+		false => SIZEOF_HEADER_32,
+	};
+
+	let mut commands = Vec::with_capacity(macho.load_commands.len());
+	let mut signed = false;
+	for lc in &macho.load_commands {
+		let size = lc.command.cmdsize();
+		let end = lc.offset + size;
+		let command = match lc.command {
+			CommandVariant::IdDylib(cmd) => Command::Dylib {
+				name: read_string(bytes, lc.offset + cmd.dylib.name as usize, end),
+				is_id: true,
+			},
+			CommandVariant::LoadDylib(cmd)
+			| CommandVariant::LoadWeakDylib(cmd)
+			| CommandVariant::ReexportDylib(cmd)
+			| CommandVariant::LazyLoadDylib(cmd)
+			| CommandVariant::LoadUpwardDylib(cmd) => Command::Dylib {
+				name: read_string(bytes, lc.offset + cmd.dylib.name as usize, end),
+				is_id: false,
+			},
+			CommandVariant::Rpath(cmd) => {
+				Command::Rpath(read_string(bytes, lc.offset + cmd.path as usize, end))
+			}
+			CommandVariant::CodeSignature(_) => {
+				signed = true;
+				Command::Other
+			}
+			_ => Command::Other,
+		};
+		commands.push(LoadCommand {
+			offset: lc.offset,
+			size,
+			command,
+		});
+	}
+
+	// The load commands can grow into the padding that precedes the first section.
+	let mut limit = bytes.len();
+	for segment in macho.segments.iter() {
+		if segment.fileoff > 0 && segment.filesize > 0 {
+			limit = limit.min(segment.fileoff as usize);
+		}
+		for (section, _) in segment.sections()? {
+			if section.offset > 0 && section.size > 0 {
+				limit = limit.min(section.offset as usize);
+			}
+		}
+	}
+
+	return Ok(Slice {
+		little_endian: macho.little_endian,
+		is_64: macho.is_64,
+		header_size,
+		commands,
+		commands_end: header_size + macho.header.sizeofcmds as usize,
+		limit,
+		signed,
+	});
+}
+
+impl Slice {
+	fn encode(&self, value: u32) -> [u8; 4] {
+		match self.little_endian {
+			true => return value.to_le_bytes(),
+			false => return value.to_be_bytes(),
+		}
+	}
+
+	// Build a load command consisting of a fixed-size part followed by a string,
+	// padded to the alignment required for the file's class.
+	fn string_command(&self, fixed: &[u8], value: &str) -> Vec<u8> {
+		let align = match self.is_64 {
+			true => 8,
+			false => 4,
+		};
+		let size = (fixed.len() + value.len() + 1).div_ceil(align) * align;
+
+		let mut result = Vec::with_capacity(size);
+		result.extend_from_slice(&fixed[0..4]);
+		result.extend_from_slice(&self.encode(size as u32));
+		result.extend_from_slice(&self.encode(fixed.len() as u32));
+		result.extend_from_slice(&fixed[12..]);
+		result.extend_from_slice(value.as_bytes());
+		result.resize(size, 0);
+		return result;
+	}
+}
+
+// Returns whether the slice is code-signed.
+fn rewrite_slice(
+	bytes: &mut [u8],
+	rewrite: &Rewrite,
+	path: &Path,
+) -> Result<bool, InstallNameError> {
+	let slice = match parse_slice(bytes) {
+		Ok(slice) => slice,
+		Err(e) => return Err(InstallNameError::FailedToParseFile(path.to_path_buf(), e)),
+	};
+
+	// dyld tries the LC_RPATH entries in order, so the new one goes before any existing ones.
+	let mut new_rpath = match &rewrite.rpath {
+		Some(rpath) => {
+			let present = slice
+				.commands
+				.iter()
+				.any(|lc| matches!(&lc.command, Command::Rpath(existing) if existing == rpath));
+			let mut fixed = [0u8; SIZEOF_RPATH_COMMAND];
+			fixed[0..4].copy_from_slice(&slice.encode(LC_RPATH));
+			match present {
+				true => None,
+				false => Some(slice.string_command(&fixed, rpath)),
+			}
+		}
+		None => None,
+	};
+
+	let mut commands: Vec<u8> = Vec::new();
+	let mut count = 0u32;
+	for lc in &slice.commands {
+		let raw = &bytes[lc.offset..lc.offset + lc.size];
+		let new_name = match &lc.command {
+			Command::Dylib { name, is_id: true } => {
+				Some(format!("{}/{}", rewrite.prefix, leaf_name(name)))
+			}
+			Command::Dylib { name, is_id: false } => rewrite.renames.get(name).cloned(),
+			Command::Rpath(_) => {
+				if let Some(rpath) = new_rpath.take() {
+					commands.extend(rpath);
+					count += 1;
+				}
+				None
+			}
+			Command::Other => None,
+		};
+		match new_name {
+			Some(name) => {
+				commands.extend(slice.string_command(&raw[..SIZEOF_DYLIB_COMMAND], &name))
+			}
+			None => commands.extend_from_slice(raw),
+		}
+		count += 1;
+	}
+	if let Some(rpath) = new_rpath {
+		commands.extend(rpath);
+		count += 1;
+	}
+
+	let new_end = slice.header_size + commands.len();
+	if new_end > slice.limit {
+		return Err(InstallNameError::NoRoom(
+			path.to_path_buf(),
+			commands.len(),
+			slice.limit - slice.header_size,
+		));
+	}
+
+	// Clear out whatever is left of the old commands if they shrank.
+	let old_end = slice.commands_end.min(bytes.len());
+	if old_end > new_end {
+		bytes[new_end..old_end].fill(0);
+	}
+	bytes[slice.header_size..new_end].copy_from_slice(&commands);
+	// ncmds and sizeofcmds, at the same offsets in both the 32-bit and 64-bit headers.
+	bytes[16..20].copy_from_slice(&slice.encode(count));
+	bytes[20..24].copy_from_slice(&slice.encode(commands.len() as u32));
+	return Ok(slice.signed);
+}
+
+// The start and end of the part of the file taken up by each architecture.
+fn slices(bytes: &[u8], path: &Path) -> Result<Vec<(usize, usize)>, InstallNameError> {
+	let parse_error = |e| InstallNameError::FailedToParseFile(path.to_path_buf(), e);
+	match Mach::parse(bytes) {
+		Ok(Mach::Binary(_)) => return Ok(vec![(0, bytes.len())]),
+		Ok(Mach::Fat(multi)) => {
+			let mut result = Vec::new();
+			for arch in multi.arches().map_err(parse_error)? {
+				let start = arch.offset as usize;
+				let end = start + arch.size as usize;
+				if end > bytes.len() {
+					return Err(parse_error(goblin::error::Error::Malformed(String::from(
+						"slice out of bounds",
+					))));
+				}
+				result.push((start, end));
+			}
+			return Ok(result);
+		}
+		Err(goblin::error::Error::BadMagic(_)) => {
+			return Err(InstallNameError::NotMachO(path.to_path_buf()))
+		}
+		Err(e) => return Err(parse_error(e)),
+	}
+}
+
+// Also tells whether any of the slices is code-signed.
+fn rewrite_bytes(
+	mut bytes: Vec<u8>,
+	rewrite: &Rewrite,
+	path: &Path,
+) -> Result<(Vec<u8>, bool), InstallNameError> {
+	let mut signed = false;
+	for (start, end) in slices(&bytes, path)? {
+		signed |= rewrite_slice(&mut bytes[start..end], rewrite, path)?;
+	}
+	return Ok((bytes, signed));
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, InstallNameError> {
+	match fs::read(path) {
+		Ok(bytes) => return Ok(bytes),
+		Err(e) => return Err(InstallNameError::FailedToReadFile(path.to_path_buf(), e)),
+	}
+}
+
+// Point the install name of a Mach-O object (LC_ID_DYLIB), as well as its references
+// to other bundled libraries, at the prefix. Like install_name_tool, this only makes use
+// of the padding after the load commands, so the rest of the file stays in place.
+// Universal binaries have each of their slices updated.
+// Returns true if the file was changed despite being code-signed, as that invalidates the signature.
+pub fn rewrite_install_names(path: &Path, rewrite: &Rewrite) -> Result<bool, InstallNameError> {
+	let original = read_file(path)?;
+	let (bytes, signed) = rewrite_bytes(original.clone(), rewrite, path)?;
+	if bytes == original {
+		return Ok(false);
+	}
+	match write_in_place(path, bytes) {
+		Ok(_) => return Ok(signed),
+		Err(e) => return Err(InstallNameError::FailedToWriteFile(path.to_path_buf(), e)),
+	}
+}
+
+// Check if rewrite_install_names() would leave the file unchanged.
+pub fn has_install_names(path: &Path, rewrite: &Rewrite) -> bool {
+	let original = match fs::read(path) {
+		Ok(bytes) => bytes,
+		Err(_) => return false,
+	};
+	match rewrite_bytes(original.clone(), rewrite, path) {
+		Ok((bytes, _)) => return bytes == original,
+		Err(_) => return false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Generated by tests/fixtures/macho/gen.py. The file ends right after the load commands.
+	fn fixture() -> (PathBuf, Vec<u8>) {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macho/libthin.dylib");
+		let bytes = fs::read(&path).unwrap();
+		return (path, bytes);
+	}
+
+	#[test]
+	fn rewrite_within_padding() {
+		let (path, mut bytes) = fixture();
+		bytes.resize(bytes.len() + 256, 0);
+
+		let renames = HashMap::from([(
+			String::from("@rpath/libfoo.dylib"),
+			String::from("@executable_path/../lib/libfoo.dylib"),
+		)]);
+		let rewrite = Rewrite {
+			prefix: "@executable_path/../lib",
+			renames: &renames,
+			rpath: None,
+		};
+		let bytes = match rewrite_bytes(bytes, &rewrite, &path) {
+			Ok((bytes, signed)) => {
+				assert!(!signed);
+				bytes
+			}
+			Err(err) => panic!("{}", err),
+		};
+
+		let macho = MachO::parse(&bytes, 0).unwrap();
+		assert_eq!(macho.name, Some("@executable_path/../lib/libthin.dylib"));
+		assert_eq!(
+			macho.libs[1..],
+			[
+				"@executable_path/../lib/libfoo.dylib",
+				"@loader_path/libopt.dylib",
+				"@executable_path/../Frameworks/liblazy.dylib",
+				"/usr/lib/libSystem.B.dylib",
+			]
+		);
+	}
+
+	#[test]
+	fn rewrite_without_room() {
+		let (path, bytes) = fixture();
+		let renames = HashMap::new();
+		let rewrite = Rewrite {
+			prefix: "@executable_path/../lib",
+			renames: &renames,
+			rpath: None,
+		};
+		match rewrite_bytes(bytes, &rewrite, &path) {
+			Err(InstallNameError::NoRoom(_, needed, available)) => assert!(needed > available),
+			Err(err) => panic!("{}", err),
+			Ok(_) => panic!("rewrite should not fit"),
+		}
+	}
+}
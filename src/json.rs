@@ -0,0 +1,168 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::parser::{DependencyKind, Object};
+use crate::process_deps::{ProcessingResult, ProcessingStatus};
+use crate::resolver::{Library, Status};
+use crate::settings::Settings;
+
+// The output is simple enough that pulling in serde is not worth it.
+pub fn escape(value: &str) -> String {
+	let mut result = String::with_capacity(value.len() + 2);
+	result.push('"');
+	for c in value.chars() {
+		match c {
+			'"' => result.push_str("\\\""),
+			'\\' => result.push_str("\\\\"),
+			'\n' => result.push_str("\\n"),
+			'\r' => result.push_str("\\r"),
+			'\t' => result.push_str("\\t"),
+			c if (c as u32) < 0x20 => {
+				let _ = write!(result, "\\u{:04x}", c as u32);
+			}
+			c => result.push(c),
+		}
+	}
+	result.push('"');
+	return result;
+}
+
+fn escape_opt(value: Option<&str>) -> String {
+	match value {
+		Some(value) => return escape(value),
+		None => return String::from("null"),
+	}
+}
+
+fn status_name(status: &Status) -> &'static str {
+	match status {
+		Status::Ignored(_) => return "ignored",
+		Status::FailedToResolve => return "failed",
+		Status::Resolved(_, _) => return "resolved",
+		Status::Unreadable(_, _, _) => return "unreadable",
+		Status::LinkerScript(_, _) => return "linker-script",
+		Status::ApiSet(_) => return "api-set",
+	}
+}
+
+fn action_name(status: &ProcessingStatus) -> Option<&'static str> {
+	match status {
+		ProcessingStatus::Skipped => return Some("skipped"),
+		ProcessingStatus::Failed => return Some("failed"),
+		ProcessingStatus::Success => return Some("copied"),
+		ProcessingStatus::Ignored
+		| ProcessingStatus::ResolveError
+		| ProcessingStatus::Unreadable => return None,
+	}
+}
+
+fn kind_name(kind: &DependencyKind) -> &'static str {
+	match kind {
+		DependencyKind::Normal => return "normal",
+		DependencyKind::DelayLoad => return "delay-load",
+		DependencyKind::Weak => return "weak",
+	}
+}
+
+pub fn print_json(
+	executable: &Object,
+	deps: &HashMap<String, Library>,
+	result: &ProcessingResult,
+	settings: &Settings,
+) {
+	let mut out = String::new();
+	out.push_str("{\n");
+	let _ = writeln!(
+		out,
+		"\t\"executable\": {},",
+		escape(&settings.executable.to_string_lossy())
+	);
+	let _ = writeln!(out, "\t\"type\": {},", escape(executable.type_.name()));
+	let _ = writeln!(
+		out,
+		"\t\"arch\": {},",
+		escape(executable.arch.name(&executable.type_))
+	);
+	let _ = writeln!(out, "\t\"dry_run\": {},", settings.dry_run);
+
+	out.push_str("\t\"dependencies\": [");
+	for (index, (name, outcome)) in result.outcomes.iter().enumerate() {
+		let library = deps.get(name.as_str()).unwrap();
+		let (path, tier) = match &library.status {
+			Status::Resolved(path, tier)
+			| Status::Unreadable(path, tier, _)
+			| Status::LinkerScript(path, tier) => (Some(path.to_string_lossy()), Some(tier.name())),
+			_ => (None, None),
+		};
+		let error = match &library.status {
+			Status::Unreadable(_, _, err) => Some(err.to_string()),
+			_ => None,
+		};
+		let action = match settings.dry_run {
+			true => None,
+			false => action_name(outcome),
+		};
+		let requested_by: Vec<String> = library
+			.requested_by
+			.iter()
+			.map(|requester| escape(requester))
+			.collect();
+		let needs: Vec<String> = library.needs.iter().map(|dep| escape(&dep.name)).collect();
+
+		out.push_str(if index == 0 { "\n" } else { ",\n" });
+		out.push_str("\t\t{\n");
+		let _ = writeln!(out, "\t\t\t\"name\": {},", escape(name));
+		let _ = writeln!(
+			out,
+			"\t\t\t\"status\": {},",
+			escape(status_name(&library.status))
+		);
+		let _ = writeln!(out, "\t\t\t\"path\": {},", escape_opt(path.as_deref()));
+		let _ = writeln!(out, "\t\t\t\"tier\": {},", escape_opt(tier));
+		let _ = writeln!(out, "\t\t\t\"kind\": {},", escape(kind_name(&library.kind)));
+		let _ = writeln!(
+			out,
+			"\t\t\t\"requested_by\": [{}],",
+			requested_by.join(", ")
+		);
+		let _ = writeln!(out, "\t\t\t\"needs\": [{}],", needs.join(", "));
+		let _ = writeln!(out, "\t\t\t\"error\": {},", escape_opt(error.as_deref()));
+		let _ = writeln!(out, "\t\t\t\"action\": {}", escape_opt(action));
+		out.push_str("\t\t}");
+	}
+	out.push_str(if result.outcomes.is_empty() {
+		"],\n"
+	} else {
+		"\n\t],\n"
+	});
+
+	out.push_str("\t\"summary\": {\n");
+	let _ = writeln!(out, "\t\t\"successful\": {},", result.successful);
+	let _ = writeln!(
+		out,
+		"\t\t\"failed_to_resolve\": {},",
+		result.failed_to_resolve
+	);
+	let _ = writeln!(out, "\t\t\"failed_to_copy\": {},", result.failed_to_copy);
+	let _ = writeln!(out, "\t\t\"unreadable\": {}", result.unreadable);
+	out.push_str("\t}\n");
+	out.push_str("}\n");
+
+	print!("{}", out);
+}
@@ -0,0 +1,216 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	fmt::{Display, Formatter},
+	path::{Component, Path, PathBuf},
+};
+
+use crate::parser::Object;
+use crate::resolver::mingw_triplet;
+
+// Value of --set-rpath that computes the RUNPATH based on the layout.
+pub const AUTO_RUNPATH: &str = "auto";
+
+// Value of --install-name-prefix that points at the library directory relative to the executable.
+pub const AUTO_INSTALL_NAME_PREFIX: &str = "auto";
+
+pub const DEFAULT_BIN_DIR: &str = "bin";
+
+pub enum LayoutError {
+	UnknownVariable(String, String),
+	UnterminatedVariable(String),
+	NotRelative(String),
+}
+
+impl Display for LayoutError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LayoutError::UnknownVariable(template, name) => write!(
+				f,
+				"Unknown variable \"{{{}}}\" in directory template \"{}\"",
+				name, template
+			),
+			LayoutError::UnterminatedVariable(template) => write!(
+				f,
+				"Unterminated variable in directory template \"{}\"",
+				template
+			),
+			LayoutError::NotRelative(template) => write!(
+				f,
+				"Directory template \"{}\" must be a relative path inside the target directory",
+				template
+			),
+		}
+	}
+}
+
+// Where files end up, relative to the target directory.
+pub struct Layout {
+	// Directory for the executable. None means the executable is not copied.
+	pub bin_dir: Option<PathBuf>,
+	pub lib_dir: PathBuf,
+}
+
+impl Layout {
+	// Everything goes directly into the target directory. The executable is left alone.
+	pub fn flat() -> Layout {
+		return Layout {
+			bin_dir: None,
+			lib_dir: PathBuf::new(),
+		};
+	}
+}
+
+// Windows looks for DLLs next to the executable, so keep them together.
+pub fn default_lib_dir(executable: &Object) -> &'static str {
+	match executable.type_.is_exe() {
+		true => return "bin",
+		false => return "lib",
+	}
+}
+
+fn variable(name: &str, executable: &Object) -> Option<String> {
+	let type_ = &executable.type_;
+	let arch = &executable.arch;
+	match name {
+		"arch" => return Some(String::from(arch.name(type_))),
+		"lib" => return Some(String::from(arch.lib_dir(type_))),
+		"triplet" if type_.is_exe() => return Some(mingw_triplet(type_, arch)),
+		"triplet" if type_.is_macho() => return Some(format!("{}-apple-darwin", arch.name(type_))),
+		"triplet" => {
+			return Some(
+				executable
+					.multiarch
+					.clone()
+					.unwrap_or_else(|| String::from(arch.name(type_))),
+			)
+		}
+		_ => return None,
+	}
+}
+
+// Expand {arch}, {lib} and {triplet} in a directory template.
+pub fn expand_template(template: &str, executable: &Object) -> Result<PathBuf, LayoutError> {
+	let mut result = String::new();
+	let mut rest = template;
+	while let Some(start) = rest.find('{') {
+		result.push_str(&rest[..start]);
+		let end = match rest[start..].find('}') {
+			Some(end) => start + end,
+			None => return Err(LayoutError::UnterminatedVariable(String::from(template))),
+		};
+		let name = &rest[start + 1..end];
+		match variable(name, executable) {
+			Some(value) => result.push_str(&value),
+			None => {
+				return Err(LayoutError::UnknownVariable(
+					String::from(template),
+					String::from(name),
+				))
+			}
+		}
+		rest = &rest[end + 1..];
+	}
+	result.push_str(rest);
+
+	let path = PathBuf::from(result);
+	let escapes = path
+		.components()
+		.any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+	if escapes {
+		return Err(LayoutError::NotRelative(String::from(template)));
+	}
+	return Ok(path);
+}
+
+// Compute the path leading from one directory to another, both relative to the same root.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+	let from: Vec<_> = from
+		.components()
+		.filter(|c| c != &Component::CurDir)
+		.collect();
+	let to: Vec<_> = to
+		.components()
+		.filter(|c| c != &Component::CurDir)
+		.collect();
+	let common = from
+		.iter()
+		.zip(to.iter())
+		.take_while(|(a, b)| a == b)
+		.count();
+
+	let mut result = PathBuf::new();
+	for _ in common..from.len() {
+		result.push("..");
+	}
+	for component in &to[common..] {
+		result.push(component);
+	}
+	return result;
+}
+
+// The RUNPATH to set on a file placed in `dir` (relative to the target directory).
+pub fn runpath_for(value: &str, dir: &Path, layout: &Layout) -> String {
+	if value != AUTO_RUNPATH {
+		return String::from(value);
+	}
+
+	let relative = relative_path(dir, &layout.lib_dir);
+	match relative.as_os_str().is_empty() {
+		true => return String::from("$ORIGIN"),
+		false => return format!("$ORIGIN/{}", relative.to_string_lossy()),
+	}
+}
+
+// The prefix for install names of copied Mach-O libraries.
+pub fn install_name_prefix_for(
+	value: &str,
+	executable: &Path,
+	target_dir: &Path,
+	layout: &Layout,
+) -> String {
+	if value != AUTO_INSTALL_NAME_PREFIX {
+		return String::from(value.trim_end_matches('/'));
+	}
+
+	let relative = match &layout.bin_dir {
+		Some(bin_dir) => relative_path(bin_dir, &layout.lib_dir),
+		// The executable stays where it is, so go by the absolute paths.
+		None => relative_path(
+			executable.parent().unwrap_or_else(|| Path::new("/")),
+			&target_dir.join(&layout.lib_dir),
+		),
+	};
+	match relative.as_os_str().is_empty() {
+		true => return String::from("@executable_path"),
+		false => return format!("@executable_path/{}", relative.to_string_lossy()),
+	}
+}
+
+// The LC_RPATH entry needed by a file placed in `dir` for "@rpath/..." install names to work.
+// For the executable, @loader_path is the same as @executable_path.
+pub fn install_name_rpath_for(prefix: &str, dir: &Path, layout: &Layout) -> Option<String> {
+	if prefix != "@rpath" && !prefix.starts_with("@rpath/") {
+		return None;
+	}
+
+	let relative = relative_path(dir, &layout.lib_dir);
+	match relative.as_os_str().is_empty() {
+		true => return Some(String::from("@loader_path")),
+		false => return Some(format!("@loader_path/{}", relative.to_string_lossy())),
+	}
+}
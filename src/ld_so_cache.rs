@@ -0,0 +1,389 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	collections::HashMap,
+	convert::TryInto,
+	fmt::{Display, Formatter},
+	fs,
+	path::{Path, PathBuf},
+};
+
+use crate::ld_so_conf::with_sysroot;
+use crate::parser::{Arch, ObjectType, EM_LOONGARCH};
+
+extern crate goblin;
+use goblin::elf::header::*;
+
+const LD_SO_CACHE: &str = "/etc/ld.so.cache";
+
+const MAGIC_OLD: &[u8] = b"ld.so-1.7.0";
+const MAGIC_NEW: &[u8] = b"glibc-ld.so.cache1.1";
+
+// Sizes of the on-disk structures, as defined in glibc's sysdeps/generic/dl-cache.h.
+const HEADER_OLD_SIZE: usize = 16;
+const ENTRY_OLD_SIZE: usize = 12;
+const HEADER_NEW_SIZE: usize = 48;
+const ENTRY_NEW_SIZE: usize = 24;
+
+const FLAG_TYPE_MASK: i32 = 0x00ff;
+const FLAG_ELF: i32 = 0x0001;
+const FLAG_ELF_LIBC6: i32 = 0x0003;
+const FLAG_REQUIRED_MASK: i32 = 0xff00;
+const FLAG_SPARC_LIB64: i32 = 0x0100;
+const FLAG_X8664_LIB64: i32 = 0x0300;
+const FLAG_S390_LIB64: i32 = 0x0400;
+const FLAG_POWERPC_LIB64: i32 = 0x0500;
+const FLAG_MIPS64_LIBN64: i32 = 0x0700;
+const FLAG_X8664_LIBX32: i32 = 0x0800;
+const FLAG_ARM_LIBHF: i32 = 0x0900;
+const FLAG_AARCH64_LIB64: i32 = 0x0a00;
+const FLAG_ARM_LIBSF: i32 = 0x0b00;
+const FLAG_MIPS_LIB32_NAN2008: i32 = 0x0c00;
+const FLAG_MIPS64_LIBN64_NAN2008: i32 = 0x0e00;
+const FLAG_RISCV_FLOAT_ABI_SOFT: i32 = 0x0f00;
+const FLAG_RISCV_FLOAT_ABI_DOUBLE: i32 = 0x1000;
+const FLAG_LARCH_FLOAT_ABI_SOFT: i32 = 0x1100;
+const FLAG_LARCH_FLOAT_ABI_DOUBLE: i32 = 0x1200;
+
+const EF_RISCV_FLOAT_ABI_MASK: u32 = 0x0006;
+const EF_RISCV_FLOAT_ABI_SOFT: u32 = 0x0000;
+const EF_LARCH_ABI_MODIFIER_MASK: u32 = 0x0007;
+const EF_LARCH_ABI_SOFT_FLOAT: u32 = 0x0001;
+
+const CACHE_ENDIAN_MASK: u8 = 0b11;
+const CACHE_ENDIAN_BIG: u8 = 0b11;
+
+pub enum LdSoCacheError {
+	FailedToReadFile(PathBuf, std::io::Error),
+	UnrecognisedFormat(PathBuf),
+	Truncated(PathBuf),
+}
+
+impl Display for LdSoCacheError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LdSoCacheError::FailedToReadFile(path, err) => write!(
+				f,
+				"Failed to read file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			LdSoCacheError::UnrecognisedFormat(path) => write!(
+				f,
+				"File \"{}\" is not in a recognised ld.so.cache format",
+				path.to_string_lossy()
+			),
+			LdSoCacheError::Truncated(path) => write!(
+				f,
+				"File \"{}\" is truncated or corrupted",
+				path.to_string_lossy()
+			),
+		}
+	}
+}
+
+struct Reader<'a> {
+	bytes: &'a [u8],
+	big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+	fn u32_at(&self, offset: usize) -> Option<u32> {
+		let slice: [u8; 4] = self.bytes.get(offset..offset + 4)?.try_into().ok()?;
+		return Some(match self.big_endian {
+			true => u32::from_be_bytes(slice),
+			false => u32::from_le_bytes(slice),
+		});
+	}
+
+	fn u64_at(&self, offset: usize) -> Option<u64> {
+		let slice: [u8; 8] = self.bytes.get(offset..offset + 8)?.try_into().ok()?;
+		return Some(match self.big_endian {
+			true => u64::from_be_bytes(slice),
+			false => u64::from_le_bytes(slice),
+		});
+	}
+
+	fn str_at(&self, offset: usize) -> Option<&'a str> {
+		let tail = self.bytes.get(offset..)?;
+		let len = tail.iter().position(|b| *b == 0)?;
+		return std::str::from_utf8(&tail[..len]).ok();
+	}
+}
+
+struct Entry<'a> {
+	flags: i32,
+	hwcap: u64,
+	key: &'a str,
+	value: &'a str,
+}
+
+// In the old format, string offsets are relative to the end of the entry table.
+fn parse_old(bytes: &[u8]) -> Option<Vec<Entry<'_>>> {
+	let reader = Reader {
+		bytes,
+		big_endian: cfg!(target_endian = "big"),
+	};
+
+	let nlibs = reader.u32_at(MAGIC_OLD.len() + 1)? as usize;
+	// Don't trust the count before checking that the file can hold that many entries.
+	if nlibs > bytes.len().saturating_sub(HEADER_OLD_SIZE) / ENTRY_OLD_SIZE {
+		return None;
+	}
+	let strings = HEADER_OLD_SIZE + nlibs * ENTRY_OLD_SIZE;
+
+	let mut entries = Vec::with_capacity(nlibs);
+	for i in 0..nlibs {
+		let offset = HEADER_OLD_SIZE + i * ENTRY_OLD_SIZE;
+		entries.push(Entry {
+			flags: reader.u32_at(offset)? as i32,
+			hwcap: 0,
+			key: reader.str_at(strings + reader.u32_at(offset + 4)? as usize)?,
+			value: reader.str_at(strings + reader.u32_at(offset + 8)? as usize)?,
+		});
+	}
+	return Some(entries);
+}
+
+// In the new format, string offsets are relative to the start of the new header.
+fn parse_new(bytes: &[u8]) -> Option<Vec<Entry<'_>>> {
+	let flags = *bytes.get(MAGIC_NEW.len() + 8)?;
+	let reader = Reader {
+		bytes,
+		big_endian: match flags & CACHE_ENDIAN_MASK {
+			CACHE_ENDIAN_BIG => true,
+			0 => cfg!(target_endian = "big"),
+			_ => false,
+		},
+	};
+
+	let nlibs = reader.u32_at(MAGIC_NEW.len())? as usize;
+	if nlibs > bytes.len().saturating_sub(HEADER_NEW_SIZE) / ENTRY_NEW_SIZE {
+		return None;
+	}
+
+	let mut entries = Vec::with_capacity(nlibs);
+	for i in 0..nlibs {
+		let offset = HEADER_NEW_SIZE + i * ENTRY_NEW_SIZE;
+		entries.push(Entry {
+			flags: reader.u32_at(offset)? as i32,
+			hwcap: reader.u64_at(offset + 16)?,
+			key: reader.str_at(reader.u32_at(offset + 4)? as usize)?,
+			value: reader.str_at(reader.u32_at(offset + 8)? as usize)?,
+		});
+	}
+	return Some(entries);
+}
+
+fn parse<'a>(bytes: &'a [u8], path: &Path) -> Result<Vec<Entry<'a>>, LdSoCacheError> {
+	let entries = if bytes.starts_with(MAGIC_NEW) {
+		parse_new(bytes)
+	} else if bytes.starts_with(MAGIC_OLD) {
+		// Caches generated by older versions of ldconfig contain both formats,
+		// with the new one following the old one (aligned to 8 bytes). Prefer it if present.
+		let nlibs = Reader {
+			bytes,
+			big_endian: cfg!(target_endian = "big"),
+		}
+		.u32_at(MAGIC_OLD.len() + 1)
+		.ok_or_else(|| LdSoCacheError::Truncated(path.to_path_buf()))?;
+
+		let new_offset = HEADER_OLD_SIZE + (nlibs as usize) * ENTRY_OLD_SIZE;
+		let new_offset = (new_offset + 7) & !7;
+		match bytes.get(new_offset..) {
+			Some(new_bytes) if new_bytes.starts_with(MAGIC_NEW) => parse_new(new_bytes),
+			_ => parse_old(bytes),
+		}
+	} else {
+		return Err(LdSoCacheError::UnrecognisedFormat(path.to_path_buf()));
+	};
+
+	return entries.ok_or_else(|| LdSoCacheError::Truncated(path.to_path_buf()));
+}
+
+// The "required" flags ldconfig assigns to libraries of given architecture.
+fn expected_flags(type_: &ObjectType, arch: &Arch) -> &'static [i32] {
+	match (arch.machine, type_.is_64()) {
+		(EM_X86_64, true) => return &[FLAG_X8664_LIB64],
+		(EM_X86_64, false) => return &[FLAG_X8664_LIBX32],
+		(EM_AARCH64, _) => return &[FLAG_AARCH64_LIB64],
+		(EM_ARM, _) => match arch.is_arm_hard_float() {
+			true => return &[FLAG_ARM_LIBHF],
+			false => return &[FLAG_ARM_LIBSF, 0],
+		},
+		(EM_RISCV, true) => match arch.flags & EF_RISCV_FLOAT_ABI_MASK {
+			EF_RISCV_FLOAT_ABI_SOFT => return &[FLAG_RISCV_FLOAT_ABI_SOFT],
+			_ => return &[FLAG_RISCV_FLOAT_ABI_DOUBLE],
+		},
+		(EM_LOONGARCH, _) => match arch.flags & EF_LARCH_ABI_MODIFIER_MASK {
+			EF_LARCH_ABI_SOFT_FLOAT => return &[FLAG_LARCH_FLOAT_ABI_SOFT],
+			_ => return &[FLAG_LARCH_FLOAT_ABI_DOUBLE],
+		},
+		(EM_PPC64, _) => return &[FLAG_POWERPC_LIB64],
+		(EM_S390, true) => return &[FLAG_S390_LIB64],
+		(EM_SPARCV9, _) => return &[FLAG_SPARC_LIB64],
+		(EM_MIPS, true) => return &[FLAG_MIPS64_LIBN64, FLAG_MIPS64_LIBN64_NAN2008],
+		(EM_MIPS, false) => return &[0, FLAG_MIPS_LIB32_NAN2008],
+		_ => return &[0],
+	}
+}
+
+fn matches_type(flags: i32, type_: &ObjectType, arch: &Arch) -> bool {
+	match flags & FLAG_TYPE_MASK {
+		FLAG_ELF | FLAG_ELF_LIBC6 => {}
+		_ => return false,
+	}
+	if !type_.is_elf() {
+		return false;
+	}
+
+	return expected_flags(type_, arch).contains(&(flags & FLAG_REQUIRED_MASK));
+}
+
+// Read the ld.so cache and return a soname -> path map
+// containing only the entries usable by objects of given type and architecture.
+pub fn read_ld_so_cache(
+	sysroot: Option<&Path>,
+	type_: &ObjectType,
+	arch: &Arch,
+) -> Result<HashMap<String, PathBuf>, LdSoCacheError> {
+	let path = with_sysroot(sysroot, Path::new(LD_SO_CACHE));
+	let bytes = match fs::read(&path) {
+		Ok(bytes) => bytes,
+		// Not having a cache is fine; it's merely an optimisation.
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+		Err(e) => return Err(LdSoCacheError::FailedToReadFile(path, e)),
+	};
+
+	let mut result = HashMap::new();
+	for entry in parse(&bytes, &path)? {
+		// Entries with hwcaps set point to CPU-specific builds (e.g. glibc-hwcaps/x86-64-v3).
+		// Those are not suitable for bundling, so only consider the baseline versions.
+		if entry.hwcap != 0 || !matches_type(entry.flags, type_, arch) {
+			continue;
+		}
+
+		// Like ld.so, use the first matching entry.
+		if !result.contains_key(entry.key) {
+			result.insert(
+				String::from(entry.key),
+				with_sysroot(sysroot, Path::new(entry.value)),
+			);
+		}
+	}
+	return Ok(result);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LIBFOO: (i32, &str, &str) = (
+		FLAG_ELF_LIBC6 | FLAG_X8664_LIB64,
+		"libfoo.so.1",
+		"/usr/lib64/libfoo.so.1",
+	);
+	const LIBBAR: (i32, &str, &str) = (FLAG_ELF_LIBC6, "libbar.so.2", "/usr/lib/libbar.so.2");
+
+	// Build a cache in the new format. String offsets are relative to the start of the header.
+	fn new_format(entries: &[(i32, &str, &str)]) -> Vec<u8> {
+		let table_end = HEADER_NEW_SIZE + entries.len() * ENTRY_NEW_SIZE;
+		let mut strings = Vec::new();
+		let mut table = Vec::new();
+		for (flags, key, value) in entries.iter() {
+			table.extend_from_slice(&flags.to_le_bytes());
+			for string in [key, value] {
+				table.extend_from_slice(&((table_end + strings.len()) as u32).to_le_bytes());
+				strings.extend_from_slice(string.as_bytes());
+				strings.push(0);
+			}
+			table.extend_from_slice(&0u32.to_le_bytes()); // osversion
+			table.extend_from_slice(&0u64.to_le_bytes()); // hwcap
+		}
+
+		let mut bytes = MAGIC_NEW.to_vec();
+		bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+		bytes.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+		bytes.push(0b10); // little endian
+		bytes.resize(HEADER_NEW_SIZE, 0);
+		bytes.extend_from_slice(&table);
+		bytes.extend_from_slice(&strings);
+		return bytes;
+	}
+
+	// Build a cache in the old format. String offsets are relative to the end of the entry table.
+	fn old_format(entries: &[(i32, &str, &str)]) -> Vec<u8> {
+		let mut strings = Vec::new();
+		let mut bytes = MAGIC_OLD.to_vec();
+		bytes.push(0);
+		bytes.extend_from_slice(&(entries.len() as u32).to_ne_bytes());
+		for (flags, key, value) in entries.iter() {
+			bytes.extend_from_slice(&flags.to_ne_bytes());
+			for string in [key, value] {
+				bytes.extend_from_slice(&(strings.len() as u32).to_ne_bytes());
+				strings.extend_from_slice(string.as_bytes());
+				strings.push(0);
+			}
+		}
+		bytes.extend_from_slice(&strings);
+		return bytes;
+	}
+
+	fn keys(bytes: &[u8]) -> Vec<(&str, &str)> {
+		match parse(bytes, Path::new("ld.so.cache")) {
+			Ok(entries) => return entries.iter().map(|e| (e.key, e.value)).collect(),
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	#[test]
+	fn new_format_only() {
+		let bytes = new_format(&[LIBFOO, LIBBAR]);
+		assert_eq!(keys(&bytes), [(LIBFOO.1, LIBFOO.2), (LIBBAR.1, LIBBAR.2)]);
+	}
+
+	#[test]
+	fn old_format_only() {
+		let bytes = old_format(&[LIBBAR]);
+		assert_eq!(keys(&bytes), [(LIBBAR.1, LIBBAR.2)]);
+	}
+
+	#[test]
+	fn old_format_followed_by_new() {
+		// The new header follows the old entry table. If present, the new format is used,
+		// even if the old one lists different entries.
+		let mut bytes = old_format(&[LIBBAR]);
+		bytes.truncate(HEADER_OLD_SIZE + ENTRY_OLD_SIZE);
+		bytes.resize((bytes.len() + 7) & !7, 0);
+		bytes.extend_from_slice(&new_format(&[LIBFOO]));
+		assert_eq!(keys(&bytes), [(LIBFOO.1, LIBFOO.2)]);
+	}
+
+	#[test]
+	fn truncated() {
+		let mut bytes = new_format(&[LIBFOO, LIBBAR]);
+		bytes.truncate(HEADER_NEW_SIZE + ENTRY_NEW_SIZE);
+		assert!(matches!(
+			parse(&bytes, Path::new("ld.so.cache")),
+			Err(LdSoCacheError::Truncated(_))
+		));
+		assert!(matches!(
+			parse(b"not a cache", Path::new("ld.so.cache")),
+			Err(LdSoCacheError::UnrecognisedFormat(_))
+		));
+	}
+}
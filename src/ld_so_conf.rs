@@ -0,0 +1,285 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	fmt::{Display, Formatter},
+	fs,
+	path::{Component, Path, PathBuf},
+	vec::Vec,
+};
+
+extern crate regex;
+use regex::Regex;
+
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+
+pub enum LdSoConfError {
+	FailedToReadFile(PathBuf, std::io::Error),
+	IncludeCycle(PathBuf),
+}
+
+impl Display for LdSoConfError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			LdSoConfError::FailedToReadFile(path, err) => write!(
+				f,
+				"Failed to read file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			LdSoConfError::IncludeCycle(path) => write!(
+				f,
+				"File \"{}\" includes itself (directly or indirectly), ignoring",
+				path.to_string_lossy()
+			),
+		}
+	}
+}
+
+pub struct LdSoConf {
+	pub dirs: Vec<PathBuf>,
+	// Problems encountered while parsing. None of these are fatal,
+	// since the configuration only serves to extend the search path.
+	pub errors: Vec<LdSoConfError>,
+}
+
+// Re-root an absolute path so that it points inside the sysroot.
+pub fn with_sysroot(sysroot: Option<&Path>, path: &Path) -> PathBuf {
+	match sysroot {
+		Some(root) => {
+			let mut result = root.to_path_buf();
+			let mut depth = 0;
+			for component in path.components() {
+				match component {
+					Component::Normal(name) => {
+						result.push(name);
+						depth += 1;
+					}
+					// Resolve ".." lexically, without ever going above the sysroot.
+					Component::ParentDir if depth > 0 => {
+						result.pop();
+						depth -= 1;
+					}
+					_ => {}
+				}
+			}
+			return result;
+		}
+		None => return path.to_path_buf(),
+	}
+}
+
+fn is_glob(pattern: &str) -> bool {
+	return pattern.contains(['*', '?', '[']);
+}
+
+fn glob_to_regex(pattern: &str) -> Option<Regex> {
+	let mut re = String::from("^");
+	let mut chars = pattern.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'*' => re.push_str(".*"),
+			'?' => re.push('.'),
+			'[' => {
+				re.push('[');
+				if let Some('!') = chars.peek() {
+					chars.next();
+					re.push('^');
+				}
+				for c in chars.by_ref() {
+					if c == ']' {
+						break;
+					}
+					if c == '\\' || c == '[' {
+						re.push('\\');
+					}
+					re.push(c);
+				}
+				re.push(']');
+			}
+			_ => re.push_str(&regex::escape(&c.to_string())),
+		}
+	}
+	re.push('$');
+	return Regex::new(&re).ok();
+}
+
+// A minimal implementation of glob(3), sufficient for "include" directives.
+fn expand_glob(pattern: &Path) -> Vec<PathBuf> {
+	let mut candidates = vec![PathBuf::new()];
+	for component in pattern.components() {
+		let name = component.as_os_str().to_string_lossy();
+		if !is_glob(&name) {
+			for candidate in candidates.iter_mut() {
+				candidate.push(component);
+			}
+			continue;
+		}
+
+		let re = match glob_to_regex(&name) {
+			Some(re) => re,
+			None => return vec![],
+		};
+
+		let mut matches = Vec::new();
+		for candidate in &candidates {
+			let entries = match fs::read_dir(candidate) {
+				Ok(entries) => entries,
+				Err(_) => continue,
+			};
+			let mut found: Vec<PathBuf> = entries
+				.flatten()
+				.filter(|entry| {
+					let entry_name = entry.file_name();
+					let entry_name = entry_name.to_string_lossy();
+					// Like glob(3), do not match hidden files unless the pattern asks for them.
+					(!entry_name.starts_with('.') || name.starts_with('.'))
+						&& re.is_match(&entry_name)
+				})
+				.map(|entry| entry.path())
+				.collect();
+			found.sort();
+			matches.append(&mut found);
+		}
+		candidates = matches;
+	}
+
+	return candidates
+		.into_iter()
+		.filter(|path| path.exists())
+		.collect();
+}
+
+struct Parser<'a> {
+	sysroot: Option<&'a Path>,
+	stack: Vec<PathBuf>,
+	result: LdSoConf,
+}
+
+impl<'a> Parser<'a> {
+	fn add_dir(&mut self, dir: &str) {
+		// Old versions of ldconfig allowed specifying the library type as "DIR=TYPE".
+		let dir = match dir.rfind('=') {
+			Some(pos) => &dir[..pos],
+			None => dir,
+		};
+		let dir = dir.trim_end_matches('/');
+		if dir.is_empty() {
+			return;
+		}
+
+		let path = with_sysroot(self.sysroot, Path::new(dir));
+		if !self.result.dirs.contains(&path) {
+			self.result.dirs.push(path);
+		}
+	}
+
+	fn include(&mut self, pattern: &str, current_dir: &Path) {
+		let pattern = Path::new(pattern);
+		let pattern = match pattern.is_absolute() {
+			true => with_sysroot(self.sysroot, pattern),
+			false => current_dir.join(pattern),
+		};
+
+		for file in expand_glob(&pattern) {
+			self.parse_file(&file);
+		}
+	}
+
+	fn parse_file(&mut self, file: &Path) {
+		let canonical = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+		if self.stack.contains(&canonical) {
+			self.result
+				.errors
+				.push(LdSoConfError::IncludeCycle(file.to_path_buf()));
+			return;
+		}
+
+		let contents = match fs::read_to_string(file) {
+			Ok(contents) => contents,
+			Err(e) => {
+				self.result
+					.errors
+					.push(LdSoConfError::FailedToReadFile(file.to_path_buf(), e));
+				return;
+			}
+		};
+		let current_dir = file.parent().unwrap_or_else(|| Path::new("/"));
+
+		self.stack.push(canonical);
+		for line in contents.lines() {
+			let line = match line.find('#') {
+				Some(pos) => &line[..pos],
+				None => line,
+			};
+			let line = line.trim();
+
+			let mut words = line.split_whitespace();
+			match words.next() {
+				None => { /* empty line */ }
+				Some("include") => {
+					for pattern in words {
+						self.include(pattern, current_dir);
+					}
+				}
+				Some("hwcap") => { /* obsolete, ignored by ldconfig */ }
+				Some(_) => self.add_dir(line),
+			}
+		}
+		self.stack.pop();
+	}
+}
+
+pub fn parse_ld_so_conf(sysroot: Option<&Path>) -> LdSoConf {
+	let mut parser = Parser {
+		sysroot,
+		stack: Vec::new(),
+		result: LdSoConf {
+			dirs: Vec::new(),
+			errors: Vec::new(),
+		},
+	};
+
+	let conf = with_sysroot(sysroot, Path::new(LD_SO_CONF));
+	// Not having an ld.so.conf at all is perfectly fine.
+	if conf.exists() {
+		parser.parse_file(&conf);
+	}
+
+	return parser.result;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sysroot_normalization() {
+		let sysroot = Some(Path::new("/sysroot"));
+		assert_eq!(
+			with_sysroot(sysroot, Path::new("/usr/lib/../lib64/./x86_64")),
+			PathBuf::from("/sysroot/usr/lib64/x86_64")
+		);
+		assert_eq!(
+			with_sysroot(sysroot, Path::new("/usr/../../../etc")),
+			PathBuf::from("/sysroot/etc")
+		);
+		assert_eq!(
+			with_sysroot(None, Path::new("/usr/lib/../lib64")),
+			PathBuf::from("/usr/lib/../lib64")
+		);
+	}
+}
@@ -0,0 +1,216 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	vec::Vec,
+};
+
+// Linker scripts installed in place of libraries are tiny.
+// Anything bigger than this is not worth reading.
+const MAX_SCRIPT_SIZE: u64 = 64 * 1024;
+
+pub enum ScriptInput {
+	// A file referenced by path, e.g. "/lib64/libc.so.6".
+	File(PathBuf),
+	// A library to be looked up in the search path, e.g. "-lfoo" or "libfoo.so.1".
+	Library(String),
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+	let mut tokens = Vec::new();
+	let mut current = String::new();
+
+	let mut chars = text.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'/' if chars.peek() == Some(&'*') => {
+				chars.next();
+				let mut previous = ' ';
+				for c in chars.by_ref() {
+					if previous == '*' && c == '/' {
+						break;
+					}
+					previous = c;
+				}
+				if !current.is_empty() {
+					tokens.push(std::mem::take(&mut current));
+				}
+			}
+			'(' | ')' | ',' | ';' => {
+				if !current.is_empty() {
+					tokens.push(std::mem::take(&mut current));
+				}
+				tokens.push(c.to_string());
+			}
+			'"' => { /* quotes are only used to protect special characters */ }
+			c if c.is_whitespace() => {
+				if !current.is_empty() {
+					tokens.push(std::mem::take(&mut current));
+				}
+			}
+			c => current.push(c),
+		}
+	}
+	if !current.is_empty() {
+		tokens.push(current);
+	}
+	return tokens;
+}
+
+fn classify(token: &str) -> ScriptInput {
+	if let Some(name) = token.strip_prefix("-l") {
+		return ScriptInput::Library(format!("lib{}.so", name));
+	}
+	match token.contains('/') {
+		true => return ScriptInput::File(PathBuf::from(token)),
+		false => return ScriptInput::Library(String::from(token)),
+	}
+}
+
+// Parse the parenthesised list of a GROUP, INPUT or AS_NEEDED command.
+// Returns the index of the first token following the list.
+fn parse_list(tokens: &[String], start: usize, inputs: &mut Vec<ScriptInput>) -> Option<usize> {
+	if tokens.get(start)? != "(" {
+		return None;
+	}
+
+	let mut index = start + 1;
+	loop {
+		let token = tokens.get(index)?;
+		match token.as_str() {
+			")" => return Some(index + 1),
+			"," => index += 1,
+			"AS_NEEDED" => index = parse_list(tokens, index + 1, inputs)?,
+			// Static archives are linked into the object itself; nothing to do at run time.
+			_ if token.ends_with(".a") => index += 1,
+			_ => {
+				inputs.push(classify(token));
+				index += 1;
+			}
+		}
+	}
+}
+
+// Skip over a parenthesised argument list, e.g. that of OUTPUT_FORMAT.
+fn skip_list(tokens: &[String], start: usize) -> Option<usize> {
+	let mut depth = 0;
+	for (index, token) in tokens.iter().enumerate().skip(start) {
+		match token.as_str() {
+			"(" => depth += 1,
+			")" => {
+				depth -= 1;
+				if depth == 0 {
+					return Some(index + 1);
+				}
+			}
+			_ => {}
+		}
+	}
+	return None;
+}
+
+// Returns None if the text does not look like a linker script.
+pub fn parse_linker_script(text: &str) -> Option<Vec<ScriptInput>> {
+	let tokens = tokenize(text);
+	let mut inputs = Vec::new();
+	let mut found_command = false;
+
+	let mut index = 0;
+	while index < tokens.len() {
+		let followed_by_list = tokens.get(index + 1).map(|t| t.as_str()) == Some("(");
+		index = match (tokens[index].as_str(), followed_by_list) {
+			("GROUP", true) | ("INPUT", true) => {
+				found_command = true;
+				parse_list(&tokens, index + 1, &mut inputs)?
+			}
+			(_, true) => skip_list(&tokens, index + 1)?,
+			(";", false) => index + 1,
+			// Stray words outside of any command mean this is not a linker script.
+			(_, false) => return None,
+		};
+	}
+
+	match found_command {
+		true => return Some(inputs),
+		false => return None,
+	}
+}
+
+// Returns None if the file is not a linker script (or cannot be read).
+pub fn read_linker_script(path: &Path) -> Option<Vec<ScriptInput>> {
+	let size = fs::metadata(path).ok()?.len();
+	if size > MAX_SCRIPT_SIZE {
+		return None;
+	}
+
+	let bytes = fs::read(path).ok()?;
+	if bytes.starts_with(b"\x7fELF") || bytes.contains(&0) {
+		return None;
+	}
+	let text = String::from_utf8(bytes).ok()?;
+	return parse_linker_script(&text);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(text: &str) -> Option<Vec<String>> {
+		let inputs = parse_linker_script(text)?;
+		return Some(
+			inputs
+				.iter()
+				.map(|input| match input {
+					ScriptInput::File(path) => format!("file {}", path.to_string_lossy()),
+					ScriptInput::Library(name) => format!("library {}", name),
+				})
+				.collect(),
+		);
+	}
+
+	#[test]
+	fn glibc_style_script() {
+		let text = concat!(
+			"/* GNU ld script\n",
+			"   Use the shared library, but some functions are only in\n",
+			"   the static library, so try that secondarily.  */\n",
+			"OUTPUT_FORMAT(elf64-x86-64)\n",
+			"GROUP ( /lib64/libc.so.6 /usr/lib64/libc_nonshared.a ",
+			"AS_NEEDED ( /lib64/ld-linux-x86-64.so.2 ) )\n",
+		);
+		assert_eq!(
+			parse(text).unwrap(),
+			["file /lib64/libc.so.6", "file /lib64/ld-linux-x86-64.so.2"]
+		);
+	}
+
+	#[test]
+	fn library_names() {
+		assert_eq!(
+			parse("INPUT(-lfoo, libbar.so.1); /* trailing comment */").unwrap(),
+			["library libfoo.so", "library libbar.so.1"]
+		);
+	}
+
+	#[test]
+	fn not_a_script() {
+		assert!(parse("just some text").is_none());
+		assert!(parse("OUTPUT_FORMAT(elf64-x86-64)").is_none());
+		assert!(parse("GROUP ( /lib64/libc.so.6").is_none());
+	}
+}
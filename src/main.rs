@@ -19,24 +19,73 @@ use std::process::exit;
 #[macro_use]
 extern crate lazy_static;
 
+mod api_set;
+
+mod dot;
+use dot::print_dot;
+
 mod exit_status;
 use exit_status::*;
 
+mod install_name;
+use install_name::bundled_install_names;
+
+mod json;
+use json::print_json;
+
+mod layout;
+use layout::install_name_prefix_for;
+
+mod ld_so_cache;
+use ld_so_cache::read_ld_so_cache;
+
+mod ld_so_conf;
+use ld_so_conf::parse_ld_so_conf;
+
+mod linker_script;
+
+mod manifest;
+use manifest::{load_manifest, update_manifest};
+
 mod parser;
 use parser::get_deps;
 
+mod permissions;
+
+mod preserve;
+
 mod process_deps;
+use process_deps::bundle_paths;
+use process_deps::check_deps;
 use process_deps::copy_deps;
+use process_deps::copy_executable;
 use process_deps::print_deps;
 
 mod resolver;
-use resolver::resolve_recursively;
+use resolver::{multiarch_dirs, resolve_recursively};
+
+mod set_rpath;
 
 mod settings;
-use settings::Settings;
+use settings::{OutputFormat, Settings};
+
+mod sha256;
+
+mod tree;
+use tree::print_tree;
 
 mod version;
+
+mod why;
 use version::*;
+use why::print_why;
+
+mod windows_dlls;
+
+mod windows_search;
+use windows_search::{check_load_order, load_redirection};
+
+mod windows_version;
 
 fn main() {
 	let mut settings = match Settings::new_from_argv() {
@@ -63,6 +112,84 @@ fn main() {
 		}
 	}
 
+	if let Err(err) = settings.compile_layout(&executable) {
+		eprintln!("{}: {}", PROGRAM_NAME, err);
+		exit(EXIT_ARGS_ERROR);
+	}
+
+	if let (false, Some(_)) = (executable.type_.is_elf(), &settings.set_rpath) {
+		eprintln!(
+			"{}: --set-rpath can only be used with ELF executables",
+			PROGRAM_NAME
+		);
+		exit(EXIT_ARGS_ERROR);
+	}
+	if let (Some(_), None, false) = (
+		&settings.set_rpath,
+		&settings.layout.bin_dir,
+		settings.dry_run,
+	) {
+		eprintln!(
+			"{}: warning: the executable is not copied (see --layout), \
+			so its RUNPATH is left unchanged",
+			PROGRAM_NAME
+		);
+	}
+
+	if let Some(value) = &settings.install_name_prefix {
+		if !executable.type_.is_macho() {
+			eprintln!(
+				"{}: --install-name-prefix can only be used with Mach-O executables",
+				PROGRAM_NAME
+			);
+			exit(EXIT_ARGS_ERROR);
+		}
+		if settings.layout.bin_dir.is_none() && !settings.dry_run {
+			eprintln!(
+				"{}: warning: the executable is not copied (see --layout), \
+				so its references to libraries are left unchanged",
+				PROGRAM_NAME
+			);
+		}
+		settings.install_name_prefix = Some(install_name_prefix_for(
+			value,
+			&settings.executable,
+			&settings.target_dir,
+			&settings.layout,
+		));
+	}
+
+	if executable.type_.is_elf() {
+		let conf = parse_ld_so_conf(settings.sysroot.as_deref());
+		for err in conf.errors {
+			eprintln!("{}: warning: {}", PROGRAM_NAME, err);
+		}
+		settings.system_dirs = conf.dirs;
+
+		if let Some(triplet) = &executable.multiarch {
+			for dir in multiarch_dirs(triplet, settings.sysroot.as_deref()) {
+				if !settings.system_dirs.contains(&dir) {
+					settings.system_dirs.push(dir);
+				}
+			}
+		}
+
+		if !settings.no_ld_cache {
+			match read_ld_so_cache(
+				settings.sysroot.as_deref(),
+				&executable.type_,
+				&executable.arch,
+			) {
+				Ok(cache) => settings.ld_cache = cache,
+				Err(err) => eprintln!("{}: warning: {}", PROGRAM_NAME, err),
+			}
+		}
+	}
+
+	if executable.type_.is_exe() {
+		settings.redirection = load_redirection(&settings);
+	}
+
 	let deps = match resolve_recursively(&executable, &settings) {
 		Ok(hm) => hm,
 		Err(msg) => {
@@ -71,10 +198,64 @@ fn main() {
 		}
 	};
 
-	let count = match settings.dry_run {
-		true => print_deps(&deps, &settings),
-		false => copy_deps(&deps, &settings),
+	if executable.type_.is_exe() {
+		check_load_order(&executable, &deps, &settings);
+	}
+
+	if let Some(library) = &settings.why {
+		if !print_why(library, &executable, &deps, &settings) {
+			eprintln!(
+				"{}: \"{}\" is not a dependency of \"{}\"",
+				PROGRAM_NAME,
+				library,
+				settings.executable_name()
+			);
+			exit(EXIT_ARGS_ERROR);
+		}
+		exit(EXIT_OK);
+	}
+
+	if let Some(prefix) = &settings.install_name_prefix {
+		settings.install_names = bundled_install_names(&deps, prefix);
+	}
+
+	let track_manifest = !settings.dry_run || settings.clean;
+	if track_manifest {
+		match load_manifest(&settings.target_dir) {
+			Ok(manifest) => settings.manifest = manifest,
+			Err(err) => {
+				eprintln!("{}: {}", PROGRAM_NAME, err);
+				exit(EXIT_COPY_FAILED);
+			}
+		}
+	}
+
+	let mut count = match (settings.dry_run, &settings.format) {
+		(true, OutputFormat::Text) if settings.tree => {
+			print_tree(&executable, &deps, &settings);
+			check_deps(&deps, &settings)
+		}
+		(true, OutputFormat::Text) => print_deps(&deps, &settings),
+		(true, _) => check_deps(&deps, &settings),
+		(false, _) => copy_deps(&deps, &settings),
 	};
+	if !settings.dry_run {
+		copy_executable(&mut count, &settings);
+	}
+	if track_manifest {
+		let bundle = bundle_paths(&deps, &settings);
+		let previous = std::mem::take(&mut settings.manifest);
+		if let Err(err) = update_manifest(&bundle, &count.copied, previous, &settings) {
+			eprintln!("{}: {}", PROGRAM_NAME, err);
+			count.failed_to_copy += 1;
+		}
+	}
+
+	match settings.format {
+		OutputFormat::Text => { /* already printed */ }
+		OutputFormat::Json => print_json(&executable, &deps, &count, &settings),
+		OutputFormat::Dot => print_dot(&executable, &deps, &settings),
+	}
 
 	if count.failed_to_resolve > 0 {
 		exit(EXIT_RESOLVE_FAILED);
@@ -82,5 +263,8 @@ fn main() {
 	if count.failed_to_copy > 0 {
 		exit(EXIT_COPY_FAILED);
 	}
+	if count.unreadable > 0 {
+		exit(EXIT_UNREADABLE_LIB);
+	}
 	exit(0);
 }
@@ -0,0 +1,411 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Display, Formatter, Write};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::process_deps::BundledFile;
+use crate::settings::{OutputFormat, Settings};
+use crate::sha256::sha256_file;
+use crate::version::*;
+
+// Stored in the root of the target directory.
+pub const MANIFEST_NAME: &str = ".copydeps-manifest";
+
+const HEADER: &str = "# copydeps manifest, version ";
+// Version 1 did not record the hash of the source file.
+const VERSION: u32 = 2;
+
+pub enum EntryKind {
+	File {
+		size: u64,
+		hash: String,
+		// The copy might have been modified (e.g. by --set-rpath), so this can differ from the above.
+		// Unknown for entries carried over from a version 1 manifest.
+		source_hash: Option<String>,
+	},
+	Symlink,
+}
+
+pub struct ManifestEntry {
+	// Relative to the target directory.
+	pub path: PathBuf,
+	pub kind: EntryKind,
+	// The file the copy was made from, or the symlink target.
+	pub source: PathBuf,
+}
+
+impl ManifestEntry {
+	fn from_disk(file: &BundledFile, target_dir: &Path) -> io::Result<ManifestEntry> {
+		if file.symlink {
+			return Ok(ManifestEntry {
+				path: file.path.clone(),
+				kind: EntryKind::Symlink,
+				source: file.source.clone(),
+			});
+		}
+
+		let full_path = target_dir.join(&file.path);
+		return Ok(ManifestEntry {
+			path: file.path.clone(),
+			kind: EntryKind::File {
+				size: fs::metadata(&full_path)?.len(),
+				hash: sha256_file(&full_path)?,
+				source_hash: Some(sha256_file(&file.source)?),
+			},
+			source: absolute(&file.source)?,
+		});
+	}
+
+	// Check if the file is still the way we left it.
+	fn matches_disk(&self, target_dir: &Path) -> bool {
+		let full_path = target_dir.join(&self.path);
+		match &self.kind {
+			EntryKind::Symlink => match fs::read_link(&full_path) {
+				Ok(target) => return target == self.source,
+				Err(_) => return false,
+			},
+			EntryKind::File { size, hash, .. } => {
+				match fs::symlink_metadata(&full_path) {
+					Ok(meta) if meta.is_file() && meta.len() == *size => {}
+					_ => return false,
+				}
+				match sha256_file(&full_path) {
+					Ok(actual) => return actual == *hash,
+					Err(_) => return false,
+				}
+			}
+		}
+	}
+
+	// Check if the file was copied from `source`, which has not changed since,
+	// and has not been changed itself.
+	pub fn is_copy_of(&self, source: &Path, target_dir: &Path) -> bool {
+		let source_hash = match &self.kind {
+			EntryKind::File {
+				source_hash: Some(source_hash),
+				..
+			} => source_hash,
+			_ => return false,
+		};
+		match absolute(source) {
+			Ok(source) if source == self.source => {}
+			_ => return false,
+		}
+		match sha256_file(source) {
+			Ok(actual) if actual == *source_hash => return self.matches_disk(target_dir),
+			_ => return false,
+		}
+	}
+}
+
+// Relative paths would be meaningless when read back from another directory.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+	match path.is_relative() {
+		true => return Ok(env::current_dir()?.join(path)),
+		false => return Ok(path.to_path_buf()),
+	}
+}
+
+pub enum ManifestError {
+	FailedToRead(PathBuf, io::Error),
+	Malformed(PathBuf, usize),
+	UnsupportedVersion(PathBuf, String),
+	FailedToWrite(PathBuf, io::Error),
+	FailedToHash(PathBuf, io::Error),
+}
+
+impl Display for ManifestError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			ManifestError::FailedToRead(path, err) => write!(
+				f,
+				"Failed to read manifest \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			ManifestError::Malformed(path, line) => write!(
+				f,
+				"Manifest \"{}\" is malformed (line {})",
+				path.to_string_lossy(),
+				line
+			),
+			ManifestError::UnsupportedVersion(path, version) => write!(
+				f,
+				"Manifest \"{}\" has unsupported version {}",
+				path.to_string_lossy(),
+				version
+			),
+			ManifestError::FailedToWrite(path, err) => write!(
+				f,
+				"Failed to write manifest \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			ManifestError::FailedToHash(path, err) => write!(
+				f,
+				"Failed to compute checksum of \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+		}
+	}
+}
+
+// Fields are tab-separated, so tabs and newlines in paths have to be escaped.
+fn escape(value: &Path) -> String {
+	let mut result = String::new();
+	for c in value.to_string_lossy().chars() {
+		match c {
+			'\\' => result.push_str("\\\\"),
+			'\t' => result.push_str("\\t"),
+			'\n' => result.push_str("\\n"),
+			c => result.push(c),
+		}
+	}
+	return result;
+}
+
+fn unescape(value: &str) -> Option<PathBuf> {
+	let mut result = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some('\\') => result.push('\\'),
+				Some('t') => result.push('\t'),
+				Some('n') => result.push('\n'),
+				_ => return None,
+			},
+			c => result.push(c),
+		}
+	}
+	return Some(PathBuf::from(result));
+}
+
+fn parse_line(line: &str, version: u32) -> Option<ManifestEntry> {
+	let mut fields: Vec<&str> = line.split('\t').collect();
+	if version == 1 && fields.len() == 5 {
+		fields.insert(3, "-");
+	}
+	if fields.len() != 6 {
+		return None;
+	}
+
+	let kind = match fields[0] {
+		"file" => EntryKind::File {
+			size: fields[1].parse().ok()?,
+			hash: String::from(fields[2]),
+			source_hash: match fields[3] {
+				"-" => None,
+				hash => Some(String::from(hash)),
+			},
+		},
+		"symlink" => EntryKind::Symlink,
+		_ => return None,
+	};
+	return Some(ManifestEntry {
+		path: unescape(fields[4])?,
+		kind,
+		source: unescape(fields[5])?,
+	});
+}
+
+// A missing manifest is not an error - it just means nothing was copied yet.
+pub fn load_manifest(target_dir: &Path) -> Result<HashMap<PathBuf, ManifestEntry>, ManifestError> {
+	let path = target_dir.join(MANIFEST_NAME);
+	let contents = match fs::read_to_string(&path) {
+		Ok(contents) => contents,
+		Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+		Err(err) => return Err(ManifestError::FailedToRead(path, err)),
+	};
+
+	if contents.is_empty() {
+		return Ok(HashMap::new());
+	}
+	let version = match contents
+		.lines()
+		.next()
+		.and_then(|line| line.strip_prefix(HEADER))
+	{
+		Some(version) => match version.parse::<u32>() {
+			Ok(number) if (1..=VERSION).contains(&number) => number,
+			_ => {
+				return Err(ManifestError::UnsupportedVersion(
+					path,
+					String::from(version),
+				))
+			}
+		},
+		None => return Err(ManifestError::Malformed(path, 1)),
+	};
+
+	let mut entries = HashMap::new();
+	for (index, line) in contents.lines().enumerate() {
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		match parse_line(line, version) {
+			Some(entry) => entries.insert(entry.path.clone(), entry),
+			None => return Err(ManifestError::Malformed(path, index + 1)),
+		};
+	}
+	return Ok(entries);
+}
+
+fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> Result<(), ManifestError> {
+	let mut out = String::new();
+	let _ = writeln!(out, "{}{}", HEADER, VERSION);
+	for entry in entries {
+		let _ = match &entry.kind {
+			EntryKind::File {
+				size,
+				hash,
+				source_hash,
+			} => write!(
+				out,
+				"file\t{}\t{}\t{}\t",
+				size,
+				hash,
+				source_hash.as_deref().unwrap_or("-")
+			),
+			EntryKind::Symlink => write!(out, "symlink\t-\t-\t-\t"),
+		};
+		let _ = writeln!(out, "{}\t{}", escape(&entry.path), escape(&entry.source));
+	}
+
+	match fs::write(path, out) {
+		Ok(_) => return Ok(()),
+		Err(err) => return Err(ManifestError::FailedToWrite(path.to_path_buf(), err)),
+	}
+}
+
+// Remove the directories left empty after removing a file, stopping at the target directory.
+fn remove_empty_parents(path: &Path, target_dir: &Path) {
+	let mut current = path.parent();
+	while let Some(dir) = current {
+		if dir.as_os_str().is_empty() || fs::remove_dir(target_dir.join(dir)).is_err() {
+			break;
+		}
+		current = dir.parent();
+	}
+}
+
+// Remove a file that is no longer part of the bundle.
+// Returns the entry back if it should stay in the manifest.
+fn clean_entry(entry: ManifestEntry, settings: &Settings) -> Option<ManifestEntry> {
+	let full_path = settings.target_dir.join(&entry.path);
+	if fs::symlink_metadata(&full_path).is_err() {
+		return None;
+	}
+	if !entry.matches_disk(&settings.target_dir) {
+		eprintln!(
+			"{}: warning: \"{}\" was modified after being copied, not removing",
+			PROGRAM_NAME,
+			full_path.to_string_lossy()
+		);
+		return None;
+	}
+
+	if settings.dry_run {
+		if let OutputFormat::Text = settings.format {
+			println!("\"{}\": (would be removed)", entry.path.to_string_lossy());
+		}
+		return Some(entry);
+	}
+	match fs::remove_file(&full_path) {
+		Ok(_) => {
+			if settings.verbose {
+				println!(
+					"\"{}\": no longer needed, removed",
+					entry.path.to_string_lossy()
+				);
+			}
+			remove_empty_parents(&entry.path, &settings.target_dir);
+			return None;
+		}
+		Err(err) => {
+			eprintln!(
+				"{}: failed to remove \"{}\": {}",
+				PROGRAM_NAME,
+				full_path.to_string_lossy(),
+				err
+			);
+			return Some(entry);
+		}
+	}
+}
+
+// Bring the manifest up to date after copying. `bundle` lists all the paths
+// that make up the bundle, while `copied` lists those written during this run.
+pub fn update_manifest(
+	bundle: &[PathBuf],
+	copied: &[BundledFile],
+	previous: HashMap<PathBuf, ManifestEntry>,
+	settings: &Settings,
+) -> Result<(), ManifestError> {
+	let mut old_entries: Vec<ManifestEntry> = previous.into_values().collect();
+	old_entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+	let mut entries = Vec::with_capacity(bundle.len());
+	for file in copied {
+		// The same file can be written more than once, e.g. when shared by two symlink chains.
+		entries.retain(|e: &ManifestEntry| e.path != file.path);
+		match ManifestEntry::from_disk(file, &settings.target_dir) {
+			Ok(entry) => entries.push(entry),
+			Err(err) => {
+				return Err(ManifestError::FailedToHash(
+					settings.target_dir.join(&file.path),
+					err,
+				))
+			}
+		}
+	}
+
+	for entry in old_entries {
+		if entries.iter().any(|e| e.path == entry.path) {
+			continue;
+		}
+		if bundle.contains(&entry.path) {
+			// Still needed, but not written this time around. If it was
+			// changed in the meantime, it's not ours to manage anymore.
+			if entry.matches_disk(&settings.target_dir) {
+				entries.push(entry);
+			}
+			continue;
+		}
+
+		match settings.clean {
+			true => entries.extend(clean_entry(entry, settings)),
+			false => {
+				// Keep track of stale files, so a later --clean can get rid of them.
+				if fs::symlink_metadata(settings.target_dir.join(&entry.path)).is_ok() {
+					entries.push(entry);
+				}
+			}
+		}
+	}
+
+	if settings.dry_run {
+		return Ok(());
+	}
+	entries.sort_by(|a, b| a.path.cmp(&b.path));
+	return write_manifest(&settings.target_dir.join(MANIFEST_NAME), &entries);
+}
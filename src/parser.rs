@@ -15,72 +15,573 @@
  * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
  */
 use std::{
+	convert::TryInto,
 	fmt::{Display, Formatter},
 	fs,
+	io::Read,
 	path::{Path, PathBuf},
 	vec::Vec,
 };
 
 extern crate goblin;
+use goblin::elf::header::*;
 use goblin::elf::Elf;
+use goblin::mach::cputype::{
+	CPU_ARCH_ABI64, CPU_ARCH_MASK, CPU_TYPE_ARM, CPU_TYPE_POWERPC, CPU_TYPE_X86,
+};
+use goblin::mach::fat::{FatArch, FAT_MAGIC, SIZEOF_FAT_ARCH};
+use goblin::mach::header::{MH_CIGAM, MH_CIGAM_64, MH_MAGIC, MH_MAGIC_64};
+use goblin::mach::load_command::CommandVariant;
+use goblin::mach::{Mach, MachO, MultiArch};
+use goblin::pe::header::*;
+use goblin::pe::optional_header::MAGIC_64;
+use goblin::pe::options::ParseOptions;
+use goblin::pe::utils::find_offset;
 use goblin::pe::PE;
 use goblin::Object as Goblin;
 
+use crate::linker_script::parse_linker_script;
+
 pub enum ObjectType {
 	Elf32,
 	Elf64,
 	Exe32,
 	Exe64,
+	MachO32,
+	MachO64,
 }
 
 impl ObjectType {
+	pub fn is_elf(&self) -> bool {
+		match self {
+			ObjectType::Elf32 | ObjectType::Elf64 => return true,
+			_ => return false,
+		}
+	}
+
 	pub fn is_exe(&self) -> bool {
 		match self {
 			ObjectType::Exe32 | ObjectType::Exe64 => return true,
 			_ => return false,
 		}
 	}
+
+	pub fn is_macho(&self) -> bool {
+		match self {
+			ObjectType::MachO32 | ObjectType::MachO64 => return true,
+			_ => return false,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			ObjectType::Elf32 => return "ELF32",
+			ObjectType::Elf64 => return "ELF64",
+			ObjectType::Exe32 => return "PE32",
+			ObjectType::Exe64 => return "PE32+",
+			ObjectType::MachO32 => return "Mach-O32",
+			ObjectType::MachO64 => return "Mach-O64",
+		}
+	}
+
+	// Name of the file format, regardless of bitness.
+	pub fn format(&self) -> &'static str {
+		match self {
+			ObjectType::Elf32 | ObjectType::Elf64 => return "ELF",
+			ObjectType::Exe32 | ObjectType::Exe64 => return "PE",
+			ObjectType::MachO32 | ObjectType::MachO64 => return "Mach-O",
+		}
+	}
+
+	pub fn is_64(&self) -> bool {
+		match self {
+			ObjectType::Elf64 | ObjectType::Exe64 | ObjectType::MachO64 => return true,
+			_ => return false,
+		}
+	}
+}
+
+// Not (yet) defined by goblin.
+pub const EM_LOONGARCH: u16 = 258;
+
+pub const EF_ARM_ABI_FLOAT_HARD: u32 = 0x400;
+
+// Details about the architecture the object was built for.
+// For ELF, these come from the file header; for PE and Mach-O, only the machine is set.
+pub struct Arch {
+	// ELF e_machine, PE COFF Machine, or Mach-O CPU type (without the ABI bits).
+	pub machine: u16,
+	pub little_endian: bool,
+	// ELF OS/ABI. Always zero for PE and Mach-O.
+	pub os_abi: u8,
+	// ELF e_flags. Always zero for PE and Mach-O.
+	pub flags: u32,
+}
+
+impl Arch {
+	pub fn name(&self, type_: &ObjectType) -> &'static str {
+		let le = self.little_endian;
+		match type_ {
+			ObjectType::Elf32 | ObjectType::Elf64 => match (self.machine, type_.is_64()) {
+				(EM_386, _) => return "i386",
+				(EM_X86_64, true) => return "x86_64",
+				(EM_X86_64, false) => return "x32",
+				(EM_AARCH64, _) => return if le { "aarch64" } else { "aarch64_be" },
+				(EM_ARM, _) => return if le { "arm" } else { "armeb" },
+				(EM_RISCV, true) => return "riscv64",
+				(EM_RISCV, false) => return "riscv32",
+				(EM_PPC, _) => return "powerpc",
+				(EM_PPC64, _) => return if le { "powerpc64le" } else { "powerpc64" },
+				(EM_S390, _) => return "s390x",
+				(EM_MIPS, true) => return if le { "mips64el" } else { "mips64" },
+				(EM_MIPS, false) => return if le { "mipsel" } else { "mips" },
+				(EM_SPARCV9, _) => return "sparc64",
+				(EM_68K, _) => return "m68k",
+				(EM_LOONGARCH, _) => return "loongarch64",
+				_ => return "unknown",
+			},
+			ObjectType::Exe32 | ObjectType::Exe64 => match self.machine {
+				COFF_MACHINE_X86 => return "i386",
+				COFF_MACHINE_X86_64 => return "x86_64",
+				COFF_MACHINE_ARM64 => return "aarch64",
+				COFF_MACHINE_ARMNT => return "arm",
+				_ => return "unknown",
+			},
+			ObjectType::MachO32 | ObjectType::MachO64 => {
+				match (self.machine as u32, type_.is_64()) {
+					(CPU_TYPE_X86, false) => return "i386",
+					(CPU_TYPE_X86, true) => return "x86_64",
+					(CPU_TYPE_ARM, false) => return "arm",
+					(CPU_TYPE_ARM, true) => return "aarch64",
+					(CPU_TYPE_POWERPC, false) => return "powerpc",
+					(CPU_TYPE_POWERPC, true) => return "powerpc64",
+					_ => return "unknown",
+				}
+			}
+		}
+	}
+
+	pub fn is_arm_hard_float(&self) -> bool {
+		return self.machine == EM_ARM && (self.flags & EF_ARM_ABI_FLOAT_HARD) != 0;
+	}
+
+	// Name of the architecture-specific library directory, as used by glibc's default search path.
+	// Other formats do not use per-architecture directories.
+	pub fn lib_dir(&self, type_: &ObjectType) -> &'static str {
+		if !type_.is_elf() {
+			return "lib";
+		}
+		match (self.machine, type_.is_64()) {
+			(EM_X86_64, false) => return "libx32",
+			(EM_RISCV, true) => return "lib64/lp64d",
+			(_, true) => return "lib64",
+			(_, false) => return "lib",
+		}
+	}
+
+	// Value of AT_PLATFORM, as used for $PLATFORM expansion.
+	fn platform(&self, type_: &ObjectType) -> &'static str {
+		match self.machine {
+			EM_386 => return "i686",
+			EM_ARM => return "v7l",
+			_ => return self.name(type_),
+		}
+	}
+
+	// Determine the Debian multiarch tuple (e.g. "x86_64-linux-gnu") for an ELF object.
+	// See https://wiki.debian.org/Multiarch/Tuples for the full list.
+	fn multiarch_triplet(&self, type_: &ObjectType) -> Option<String> {
+		let os = match self.os_abi {
+			ELFOSABI_NONE | ELFOSABI_GNU => "linux-gnu",
+			ELFOSABI_FREEBSD => "kfreebsd-gnu",
+			_ => return None,
+		};
+
+		let (cpu, abi) = match (self.machine, type_.is_64()) {
+			(EM_386, false) => ("i386", ""),
+			(EM_X86_64, true) => ("x86_64", ""),
+			(EM_X86_64, false) => ("x86_64", "x32"),
+			(EM_ARM, false) => match self.is_arm_hard_float() {
+				false => ("arm", "eabi"),
+				true => ("arm", "eabihf"),
+			},
+			(EM_MIPS, true) => (self.name(type_), "abi64"),
+			(EM_AARCH64, true)
+			| (EM_RISCV, true)
+			| (EM_PPC, false)
+			| (EM_PPC64, true)
+			| (EM_S390, true)
+			| (EM_MIPS, false)
+			| (EM_SPARCV9, true)
+			| (EM_68K, false)
+			| (EM_LOONGARCH, true) => (self.name(type_), ""),
+			_ => return None,
+		};
+
+		return Some(format!("{}-{}{}", cpu, os, abi));
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DependencyKind {
+	// A regular import, needed for the object to load at all.
+	Normal,
+	// Only loaded on first use: a PE delay-load import or a Mach-O lazy-load dylib.
+	DelayLoad,
+	// A Mach-O weak import, which is allowed to be missing at run time.
+	Weak,
+}
+
+#[derive(Clone)]
+pub struct Dependency {
+	pub name: String,
+	pub kind: DependencyKind,
+	// Set when the dependency refers to a specific file rather than a name
+	// to be looked up, as is the case for files listed in linker scripts.
+	pub path: Option<PathBuf>,
+}
+
+impl Dependency {
+	pub fn normal(name: &str) -> Dependency {
+		return Dependency {
+			name: String::from(name),
+			kind: DependencyKind::Normal,
+			path: None,
+		};
+	}
 }
 
 pub struct Object {
 	pub type_: ObjectType,
-	pub deps: Vec<String>,
+	pub arch: Arch,
+	pub deps: Vec<Dependency>,
+	pub multiarch: Option<String>,
+	// ELF SONAME or Mach-O install name (LC_ID_DYLIB).
+	pub soname: Option<String>,
+	// ELF DT_RPATH or Mach-O LC_RPATH.
+	pub rpath: Vec<PathBuf>,
+	pub runpath: Vec<PathBuf>,
 }
 
-fn get_deps_elf(elf: Elf) -> Object {
+// Expand the dynamic string tokens ($ORIGIN, $LIB, $PLATFORM) the same way ld.so does.
+// Both the "$TOKEN" and "${TOKEN}" forms are recognised.
+fn expand_dst(entry: &str, origin: &Path, type_: &ObjectType, arch: &Arch) -> String {
+	let origin = origin.to_string_lossy();
+
+	let mut result = String::from(entry);
+	for (token, value) in &[
+		("ORIGIN", origin.as_ref()),
+		("LIB", arch.lib_dir(type_)),
+		("PLATFORM", arch.platform(type_)),
+	] {
+		result = result
+			.replace(&format!("${{{}}}", token), value)
+			.replace(&format!("${}", token), value);
+	}
+	return result;
+}
+
+// DT_RPATH and DT_RUNPATH entries are colon-separated lists of directories.
+fn split_search_path(
+	entries: &[&str],
+	origin: &Path,
+	type_: &ObjectType,
+	arch: &Arch,
+) -> Vec<PathBuf> {
+	return entries
+		.iter()
+		.flat_map(|entry| entry.split(':'))
+		.filter(|dir| !dir.is_empty())
+		.map(|dir| PathBuf::from(expand_dst(dir, origin, type_, arch)))
+		.collect();
+}
+
+fn get_deps_elf(elf: Elf, filename: &Path) -> Object {
+	let type_ = if elf.is_64 {
+		ObjectType::Elf64
+	} else {
+		ObjectType::Elf32
+	};
+	let arch = Arch {
+		machine: elf.header.e_machine,
+		little_endian: elf.little_endian,
+		os_abi: elf.header.e_ident[EI_OSABI],
+		flags: elf.header.e_flags,
+	};
+	let origin = filename.parent().unwrap_or_else(|| Path::new("/"));
+
 	return Object {
-		type_: if elf.is_64 {
-			ObjectType::Elf64
-		} else {
-			ObjectType::Elf32
-		},
 		deps: elf
 			.libraries
 			.iter()
-			.map(|item| String::from(*item))
+			.map(|item| Dependency::normal(item))
 			.collect(),
+		multiarch: arch.multiarch_triplet(&type_),
+		soname: elf.soname.map(String::from),
+		rpath: split_search_path(&elf.rpaths, origin, &type_, &arch),
+		runpath: split_search_path(&elf.runpaths, origin, &type_, &arch),
+		arch,
+		type_,
 	};
 }
 
-fn get_deps_pe(exe: PE) -> Object {
+// Size of IMAGE_DELAYLOAD_DESCRIPTOR.
+const DELAY_IMPORT_DESCRIPTOR_SIZE: usize = 32;
+// If set, addresses in the descriptor are RVAs; otherwise, they're VAs.
+const DELAY_IMPORT_ATTRIBUTE_RVA_BASED: u32 = 0x1;
+
+// goblin does not parse the delay-load import directory, so do it by hand.
+fn get_delay_imports<'a>(exe: &PE, bytes: &'a [u8]) -> Vec<&'a str> {
+	let mut result = Vec::new();
+
+	let optional_header = match exe.header.optional_header {
+		Some(opt) => opt,
+		None => return result,
+	};
+	let directory = match optional_header
+		.data_directories
+		.get_delay_import_descriptor()
+	{
+		Some(dir) => dir,
+		None => return result,
+	};
+	let file_alignment = optional_header.windows_fields.file_alignment;
+	let opts = ParseOptions::default();
+	let find_offset = |rva: usize| find_offset(rva, &exe.sections, file_alignment, &opts);
+
+	let mut offset = match find_offset(directory.virtual_address as usize) {
+		Some(offset) => offset,
+		None => return result,
+	};
+	let read_u32 = |at: usize| -> Option<u32> {
+		let slice = bytes.get(at..at + 4)?;
+		return Some(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]));
+	};
+
+	// The table is terminated by an all-zeroes entry.
+	while let (Some(attributes), Some(name_addr)) = (read_u32(offset), read_u32(offset + 4)) {
+		if name_addr == 0 {
+			break;
+		}
+
+		let name_rva = match attributes & DELAY_IMPORT_ATTRIBUTE_RVA_BASED {
+			0 => (name_addr as usize).wrapping_sub(exe.image_base),
+			_ => name_addr as usize,
+		};
+		let name = find_offset(name_rva)
+			.and_then(|name_offset| bytes.get(name_offset..))
+			.and_then(|tail| {
+				let len = tail.iter().position(|b| *b == 0)?;
+				return std::str::from_utf8(&tail[..len]).ok();
+			});
+		if let Some(name) = name {
+			result.push(name);
+		}
+
+		offset += DELAY_IMPORT_DESCRIPTOR_SIZE;
+	}
+	return result;
+}
+
+fn get_deps_pe(exe: PE, bytes: &[u8]) -> Object {
+	let mut deps: Vec<Dependency> = exe
+		.libraries
+		.iter()
+		.map(|item| Dependency::normal(item))
+		.collect();
+	for name in get_delay_imports(&exe, bytes) {
+		deps.push(Dependency {
+			name: String::from(name),
+			kind: DependencyKind::DelayLoad,
+			path: None,
+		});
+	}
+
 	return Object {
 		type_: if exe.is_64 {
 			ObjectType::Exe64
 		} else {
 			ObjectType::Exe32
 		},
-		deps: exe
-			.libraries
-			.iter()
-			.map(|item| String::from(*item))
-			.collect(),
+		arch: Arch {
+			machine: exe.header.coff_header.machine,
+			little_endian: true,
+			os_abi: 0,
+			flags: 0,
+		},
+		deps,
+		multiarch: None,
+		soname: None,
+		rpath: vec![],
+		runpath: vec![],
 	};
 }
 
+// Resource type of application and assembly manifests.
+const RT_MANIFEST: u32 = 24;
+// Set in resource directory entries that point at another directory, rather than at the data.
+const RESOURCE_SUBDIRECTORY: u32 = 0x8000_0000;
+
+// goblin does not parse resources either. Walk the type / name / language levels
+// of the resource tree by hand, taking the first manifest found.
+fn get_manifest(exe: &PE, bytes: &[u8]) -> Option<String> {
+	let optional_header = exe.header.optional_header?;
+	let directory = optional_header
+		.data_directories
+		.get_resource_table()
+		.as_ref()?;
+	let file_alignment = optional_header.windows_fields.file_alignment;
+	let opts = ParseOptions::default();
+	let find_offset = |rva: usize| find_offset(rva, &exe.sections, file_alignment, &opts);
+	let read_u16 = |at: usize| -> Option<u16> {
+		let slice = bytes.get(at..at + 2)?;
+		return Some(u16::from_le_bytes([slice[0], slice[1]]));
+	};
+
+	// Offsets within the tree are relative to its root.
+	let root = find_offset(directory.virtual_address as usize)?;
+	let mut offset = root;
+	for (wanted_id, subdirectory) in [(Some(RT_MANIFEST), true), (None, true), (None, false)] {
+		// Named entries come first, followed by those identified by a number.
+		let count = read_u16(offset + 12)? as usize + read_u16(offset + 14)? as usize;
+		let entry =
+			(0..count)
+				.map(|index| offset + 16 + index * 8)
+				.find(|entry| match wanted_id {
+					Some(id) => read_u32(bytes, *entry, true) == Some(id),
+					None => true,
+				})?;
+		let target = read_u32(bytes, entry + 4, true)?;
+		if (target & RESOURCE_SUBDIRECTORY != 0) != subdirectory {
+			return None;
+		}
+		offset = root + (target & !RESOURCE_SUBDIRECTORY) as usize;
+	}
+
+	let data = find_offset(read_u32(bytes, offset, true)? as usize)?;
+	let size = read_u32(bytes, offset + 4, true)? as usize;
+	return Some(String::from_utf8_lossy(bytes.get(data..data + size)?).into_owned());
+}
+
+// Read the manifest embedded in a PE executable or DLL, if there is one.
+pub fn read_embedded_manifest(filename: &Path) -> Option<String> {
+	let bytes = fs::read(filename).ok()?;
+	match PE::parse(&bytes) {
+		Ok(exe) => return get_manifest(&exe, &bytes),
+		Err(_) => return None,
+	}
+}
+
+// Mach-O CPU types, with the ABI bits stripped, fit in the same field as ELF and PE machines.
+fn macho_machine(cputype: u32) -> u16 {
+	return (cputype & !CPU_ARCH_MASK) as u16;
+}
+
+fn macho_type(cputype: u32) -> ObjectType {
+	match cputype & CPU_ARCH_ABI64 {
+		0 => return ObjectType::MachO32,
+		_ => return ObjectType::MachO64,
+	}
+}
+
+fn macho_arch(cputype: u32, little_endian: bool) -> Arch {
+	return Arch {
+		machine: macho_machine(cputype),
+		little_endian,
+		os_abi: 0,
+		flags: 0,
+	};
+}
+
+// Expand @loader_path, which refers to the directory of the object containing the entry.
+pub fn expand_loader_path(entry: &str, origin: &Path) -> PathBuf {
+	match entry.strip_prefix("@loader_path") {
+		Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+			return PathBuf::from(format!("{}{}", origin.to_string_lossy(), rest));
+		}
+		_ => return PathBuf::from(entry),
+	}
+}
+
+fn get_deps_macho(macho: MachO) -> Object {
+	// goblin collects the names of all the dylib load commands into `libs`, preceded by
+	// a literal "self" entry, but doesn't say which command each name came from.
+	let kinds = macho
+		.load_commands
+		.iter()
+		.filter_map(|cmd| match cmd.command {
+			CommandVariant::LoadDylib(_)
+			| CommandVariant::LoadUpwardDylib(_)
+			| CommandVariant::ReexportDylib(_) => Some(DependencyKind::Normal),
+			CommandVariant::LoadWeakDylib(_) => Some(DependencyKind::Weak),
+			CommandVariant::LazyLoadDylib(_) => Some(DependencyKind::DelayLoad),
+			_ => None,
+		});
+	let deps = macho
+		.libs
+		.iter()
+		.skip(1)
+		.zip(kinds)
+		.map(|(name, kind)| Dependency {
+			name: String::from(*name),
+			kind,
+			path: None,
+		})
+		.collect();
+
+	let cputype = macho.header.cputype;
+	return Object {
+		type_: macho_type(cputype),
+		arch: macho_arch(cputype, macho.little_endian),
+		deps,
+		multiarch: None,
+		soname: macho.name.map(String::from),
+		// Expanded by the resolver, which knows where the object is and what the sysroot is.
+		rpath: macho.rpaths.iter().map(PathBuf::from).collect(),
+		runpath: vec![],
+	};
+}
+
+fn fat_arch_matches(fat: &FatArch, type_: &ObjectType, arch: &Arch) -> bool {
+	return macho_machine(fat.cputype) == arch.machine && fat.is_64() == type_.is_64();
+}
+
+// Pick the slice of a universal binary to read the dependencies from.
+// Without a preference, the first one is used.
+fn pick_slice<'a>(
+	multi: &MultiArch<'a>,
+	want: Option<(&ObjectType, &Arch)>,
+	filename: &Path,
+) -> Result<MachO<'a>, GetDepsError> {
+	let parse_error = |e| GetDepsError::FailedToParseFile(filename.to_path_buf(), e);
+
+	let index = match want {
+		Some((type_, arch)) => {
+			let arches = multi.arches().map_err(parse_error)?;
+			match arches
+				.iter()
+				.position(|fat| fat_arch_matches(fat, type_, arch))
+			{
+				Some(index) => index,
+				None => {
+					return Err(GetDepsError::NoMatchingArchitecture(
+						filename.to_path_buf(),
+						String::from(arch.name(type_)),
+					))
+				}
+			}
+		}
+		None => 0,
+	};
+	return multi.get(index).map_err(parse_error);
+}
+
 pub enum GetDepsError {
 	FailedToOpenFile(PathBuf, std::io::Error),
 	FailedToParseFile(PathBuf, goblin::error::Error),
 	UnsupportedObjectType(PathBuf, String),
+	LinkerScript(PathBuf),
+	NoMatchingArchitecture(PathBuf, String),
 }
 
 impl Display for GetDepsError {
@@ -104,11 +605,45 @@ impl Display for GetDepsError {
 				path.to_string_lossy(),
 				objtype
 			),
+			GetDepsError::LinkerScript(path) => write!(
+				f,
+				"File \"{}\" is a GNU ld linker script, not a shared object",
+				path.to_string_lossy()
+			),
+			GetDepsError::NoMatchingArchitecture(path, arch) => write!(
+				f,
+				"Universal binary \"{}\" does not contain code for {}",
+				path.to_string_lossy(),
+				arch
+			),
 		}
 	}
 }
 
+fn is_linker_script(bytes: &[u8]) -> bool {
+	return std::str::from_utf8(bytes)
+		.ok()
+		.and_then(parse_linker_script)
+		.is_some();
+}
+
 pub fn get_deps(filename: &Path) -> Result<Object, GetDepsError> {
+	return read_object(filename, None);
+}
+
+// Like get_deps(), but for universal binaries, read the slice matching the given architecture.
+pub fn get_deps_matching(
+	filename: &Path,
+	type_: &ObjectType,
+	arch: &Arch,
+) -> Result<Object, GetDepsError> {
+	return read_object(filename, Some((type_, arch)));
+}
+
+fn read_object(
+	filename: &Path,
+	want: Option<(&ObjectType, &Arch)>,
+) -> Result<Object, GetDepsError> {
 	let bytes = match fs::read(filename) {
 		Ok(bytes) => bytes,
 		Err(e) => {
@@ -124,8 +659,16 @@ pub fn get_deps(filename: &Path) -> Result<Object, GetDepsError> {
 	};
 
 	match object {
-		Goblin::Elf(elf) => Ok(get_deps_elf(elf)),
-		Goblin::PE(pe) => Ok(get_deps_pe(pe)),
+		Goblin::Elf(elf) => Ok(get_deps_elf(elf, filename)),
+		Goblin::PE(pe) => Ok(get_deps_pe(pe, &bytes)),
+		Goblin::Mach(Mach::Binary(macho)) => Ok(get_deps_macho(macho)),
+		Goblin::Mach(Mach::Fat(multi)) => {
+			let macho = pick_slice(&multi, want, filename)?;
+			Ok(get_deps_macho(macho))
+		}
+		Goblin::Unknown(_) if is_linker_script(&bytes) => {
+			Err(GetDepsError::LinkerScript(filename.to_path_buf()))
+		}
 		_ => Err(GetDepsError::UnsupportedObjectType(
 			filename.to_path_buf(),
 			obj_type_name(&object),
@@ -133,6 +676,101 @@ pub fn get_deps(filename: &Path) -> Result<Object, GetDepsError> {
 	}
 }
 
+// Enough to cover the ELF header, the DOS, COFF and optional PE headers,
+// or the Mach-O header and the list of slices in a universal binary.
+const HEADER_PEEK_SIZE: u64 = 4096;
+
+fn read_u32(bytes: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+	let slice: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+	match little_endian {
+		true => return Some(u32::from_le_bytes(slice)),
+		false => return Some(u32::from_be_bytes(slice)),
+	}
+}
+
+// Determine the object type and architecture of a file without parsing it in full.
+// Universal binaries contain code for more than one architecture; all of them are returned.
+pub fn get_archs(filename: &Path) -> Result<Vec<(ObjectType, Arch)>, GetDepsError> {
+	let mut bytes = Vec::new();
+	let read = fs::File::open(filename)
+		.and_then(|file| file.take(HEADER_PEEK_SIZE).read_to_end(&mut bytes));
+	if let Err(e) = read {
+		return Err(GetDepsError::FailedToOpenFile(filename.to_path_buf(), e));
+	}
+
+	if bytes.starts_with(b"\x7fELF") {
+		let header = match Elf::parse_header(&bytes) {
+			Ok(header) => header,
+			Err(e) => return Err(GetDepsError::FailedToParseFile(filename.to_path_buf(), e)),
+		};
+		let type_ = match header.e_ident[EI_CLASS] {
+			ELFCLASS64 => ObjectType::Elf64,
+			_ => ObjectType::Elf32,
+		};
+		let arch = Arch {
+			machine: header.e_machine,
+			little_endian: header.e_ident[EI_DATA] == ELFDATA2LSB,
+			os_abi: header.e_ident[EI_OSABI],
+			flags: header.e_flags,
+		};
+		return Ok(vec![(type_, arch)]);
+	}
+
+	if bytes.starts_with(b"MZ") {
+		let header = match goblin::pe::header::Header::parse(&bytes) {
+			Ok(header) => header,
+			Err(e) => return Err(GetDepsError::FailedToParseFile(filename.to_path_buf(), e)),
+		};
+		let type_ = match header.optional_header {
+			Some(opt) if opt.standard_fields.magic == MAGIC_64 => ObjectType::Exe64,
+			_ => ObjectType::Exe32,
+		};
+		let arch = Arch {
+			machine: header.coff_header.machine,
+			little_endian: true,
+			os_abi: 0,
+			flags: 0,
+		};
+		return Ok(vec![(type_, arch)]);
+	}
+
+	let truncated = || {
+		GetDepsError::FailedToParseFile(
+			filename.to_path_buf(),
+			goblin::error::Error::Malformed(String::from("truncated Mach-O header")),
+		)
+	};
+	match read_u32(&bytes, 0, false) {
+		Some(MH_MAGIC) | Some(MH_MAGIC_64) | Some(MH_CIGAM) | Some(MH_CIGAM_64) => {
+			let little_endian = bytes[0] != 0xfe;
+			let cputype = read_u32(&bytes, 4, little_endian).ok_or_else(truncated)?;
+			return Ok(vec![(
+				macho_type(cputype),
+				macho_arch(cputype, little_endian),
+			)]);
+		}
+		Some(FAT_MAGIC) => {
+			// The fat header and the list of slices are always big-endian.
+			let count = read_u32(&bytes, 4, false).ok_or_else(truncated)? as usize;
+			let mut result = Vec::new();
+			for index in 0..count {
+				let offset = 8 + index * SIZEOF_FAT_ARCH;
+				let cputype = read_u32(&bytes, offset, false).ok_or_else(truncated)?;
+				// Every architecture Apple ever shipped for is little-endian, except for PowerPC.
+				let little_endian = macho_machine(cputype) != CPU_TYPE_POWERPC as u16;
+				result.push((macho_type(cputype), macho_arch(cputype, little_endian)));
+			}
+			return Ok(result);
+		}
+		_ => {}
+	}
+
+	return Err(GetDepsError::UnsupportedObjectType(
+		filename.to_path_buf(),
+		String::from("Unknown"),
+	));
+}
+
 fn obj_type_name(obj: &goblin::Object) -> String {
 	match obj {
 		Goblin::Elf(_) => "Elf".to_string(),
@@ -142,3 +780,113 @@ fn obj_type_name(obj: &goblin::Object) -> String {
 		Goblin::Unknown(magic) => format!("Unknown (magic: {})", magic),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Generated by tests/fixtures/macho/gen.py.
+	fn fixture(name: &str) -> PathBuf {
+		return Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("tests/fixtures/macho")
+			.join(name);
+	}
+
+	fn read(path: &Path, want: Option<(&ObjectType, &Arch)>) -> Object {
+		match read_object(path, want) {
+			Ok(obj) => return obj,
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	fn dep_names(obj: &Object) -> Vec<&str> {
+		return obj.deps.iter().map(|dep| dep.name.as_str()).collect();
+	}
+
+	#[test]
+	fn thin_macho() {
+		let path = fixture("libthin.dylib");
+		let obj = read(&path, None);
+		assert!(matches!(obj.type_, ObjectType::MachO64));
+		assert_eq!(obj.arch.name(&obj.type_), "x86_64");
+		assert_eq!(obj.soname.as_deref(), Some("@rpath/libthin.dylib"));
+		assert_eq!(
+			dep_names(&obj),
+			[
+				"@rpath/libfoo.dylib",
+				"@loader_path/libopt.dylib",
+				"@executable_path/../Frameworks/liblazy.dylib",
+				"/usr/lib/libSystem.B.dylib",
+			]
+		);
+		let kinds: Vec<DependencyKind> = obj.deps.iter().map(|dep| dep.kind).collect();
+		assert!(matches!(
+			kinds[..],
+			[
+				DependencyKind::Normal,
+				DependencyKind::Weak,
+				DependencyKind::DelayLoad,
+				DependencyKind::Normal,
+			]
+		));
+
+		// LC_RPATH entries are kept as-is, to be expanded by the resolver.
+		assert_eq!(
+			obj.rpath,
+			[
+				PathBuf::from("@loader_path/../lib"),
+				PathBuf::from("@executable_path/../Frameworks"),
+			]
+		);
+	}
+
+	#[test]
+	fn fat_macho() {
+		let path = fixture("libfat.dylib");
+		let archs = match get_archs(&path) {
+			Ok(archs) => archs,
+			Err(err) => panic!("{}", err),
+		};
+		let names: Vec<&str> = archs.iter().map(|(type_, arch)| arch.name(type_)).collect();
+		assert_eq!(names, ["aarch64", "x86_64"]);
+
+		// Without a preference, the first slice is used.
+		assert_eq!(dep_names(&read(&path, None)), ["@rpath/libarm.dylib"]);
+
+		let (type_, arch) = &archs[1];
+		let obj = read(&path, Some((type_, arch)));
+		assert_eq!(obj.arch.name(&obj.type_), "x86_64");
+		assert_eq!(dep_names(&obj), ["@rpath/libx86.dylib"]);
+
+		let thin = read(&fixture("libthin.dylib"), None);
+		let ppc = Arch {
+			machine: CPU_TYPE_POWERPC as u16,
+			..thin.arch
+		};
+		assert!(matches!(
+			read_object(&path, Some((&ObjectType::MachO32, &ppc))),
+			Err(GetDepsError::NoMatchingArchitecture(_, _))
+		));
+	}
+
+	#[test]
+	fn loader_path_expansion() {
+		let origin = Path::new("/opt/app/lib");
+		assert_eq!(
+			expand_loader_path("@loader_path", origin),
+			PathBuf::from("/opt/app/lib")
+		);
+		assert_eq!(
+			expand_loader_path("@loader_path/../Frameworks", origin),
+			PathBuf::from("/opt/app/lib/../Frameworks")
+		);
+		assert_eq!(
+			expand_loader_path("@loader_pathological", origin),
+			PathBuf::from("@loader_pathological")
+		);
+		assert_eq!(
+			expand_loader_path("/usr/local/lib", origin),
+			PathBuf::from("/usr/local/lib")
+		);
+	}
+}
@@ -0,0 +1,58 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{fs, path::Path};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+// If the owner cannot write to the file, return the permissions that allow it.
+#[cfg(unix)]
+pub fn owner_writable(permissions: &fs::Permissions) -> Option<fs::Permissions> {
+	match permissions.mode() & 0o200 {
+		0 => return Some(fs::Permissions::from_mode(permissions.mode() | 0o200)),
+		_ => return None,
+	}
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::permissions_set_readonly_false)]
+pub fn owner_writable(permissions: &fs::Permissions) -> Option<fs::Permissions> {
+	match permissions.readonly() {
+		true => {
+			let mut writable = permissions.clone();
+			writable.set_readonly(false);
+			return Some(writable);
+		}
+		false => return None,
+	}
+}
+
+// Overwrite the contents of a copied file.
+pub fn write_in_place(path: &Path, bytes: Vec<u8>) -> std::io::Result<()> {
+	// Libraries are sometimes installed read-only. The copy is ours to modify, though.
+	let permissions = fs::metadata(path)?.permissions();
+	let writable = owner_writable(&permissions);
+	if let Some(writable) = &writable {
+		let _ = fs::set_permissions(path, writable.clone());
+	}
+
+	let result = fs::write(path, bytes);
+	if writable.is_some() {
+		let _ = fs::set_permissions(path, permissions);
+	}
+	return result;
+}
@@ -0,0 +1,215 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, FileTimes};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::{chown, MetadataExt};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+extern crate xattr;
+
+use crate::permissions::owner_writable;
+
+// Used when --preserve is given without a list, same as "cp --preserve".
+const DEFAULT_ATTRIBUTES: &str = "mode,ownership,timestamps";
+
+#[derive(Default)]
+pub struct Preserve {
+	pub mode: bool,
+	pub ownership: bool,
+	// Like cp, only complain about failing to change the owner if it was asked for by name,
+	// as it's expected to fail for unprivileged users.
+	pub report_ownership: bool,
+	pub timestamps: bool,
+	pub xattr: bool,
+}
+
+impl Preserve {
+	pub fn parse(list: Option<&str>) -> Result<Preserve, String> {
+		let mut result = Preserve::default();
+		for attribute in list.unwrap_or(DEFAULT_ATTRIBUTES).split(',') {
+			match attribute {
+				"mode" => result.mode = true,
+				"ownership" if list.is_some() => {
+					result.ownership = true;
+					result.report_ownership = true;
+				}
+				"ownership" => result.ownership = true,
+				"timestamps" => result.timestamps = true,
+				"xattr" => result.xattr = true,
+				"all" => {
+					result.mode = true;
+					result.ownership = true;
+					result.timestamps = true;
+					result.xattr = true;
+				}
+				_ => return Err(String::from(attribute)),
+			}
+		}
+		return Ok(result);
+	}
+
+	pub fn any(&self) -> bool {
+		return self.mode || self.ownership || self.timestamps || self.xattr;
+	}
+}
+
+pub struct PreserveError {
+	path: PathBuf,
+	attribute: String,
+	err: io::Error,
+}
+
+impl Display for PreserveError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(
+			f,
+			"Failed to preserve {} of \"{}\": {}",
+			self.attribute,
+			self.path.to_string_lossy(),
+			self.err
+		)
+	}
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> io::Error {
+	return io::Error::new(io::ErrorKind::Unsupported, "not supported on this platform");
+}
+
+#[cfg(unix)]
+fn copy_ownership(meta: &fs::Metadata, destination: &Path) -> io::Result<()> {
+	// Unprivileged users can't give files away, but can still change the group.
+	return chown(destination, Some(meta.uid()), Some(meta.gid()))
+		.or_else(|_| chown(destination, None, Some(meta.gid())));
+}
+
+#[cfg(not(unix))]
+fn copy_ownership(_meta: &fs::Metadata, _destination: &Path) -> io::Result<()> {
+	return Err(unsupported());
+}
+
+#[cfg(unix)]
+fn copy_xattrs(source: &Path, destination: &Path) -> Vec<(String, io::Error)> {
+	let names = match xattr::list(source) {
+		Ok(names) => names,
+		Err(err) => return vec![(String::from("extended attributes"), err)],
+	};
+
+	let mut errors = Vec::new();
+	for name in names {
+		let label = format!("extended attribute \"{}\"", name.to_string_lossy());
+		let result = match xattr::get(source, &name) {
+			Ok(Some(value)) => xattr::set(destination, &name, &value),
+			Ok(None) => continue,
+			Err(err) => Err(err),
+		};
+		if let Err(err) = result {
+			errors.push((label, err));
+		}
+	}
+	return errors;
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_source: &Path, _destination: &Path) -> Vec<(String, io::Error)> {
+	return vec![(String::from("extended attributes"), unsupported())];
+}
+
+// Changing the timestamps only requires owning the file, so there's no need to open it for writing.
+#[cfg(unix)]
+fn open_for_timestamps(path: &Path) -> io::Result<File> {
+	return File::open(path);
+}
+
+#[cfg(not(unix))]
+fn open_for_timestamps(path: &Path) -> io::Result<File> {
+	return fs::OpenOptions::new().write(true).open(path);
+}
+
+// Copy the requested attributes of `source` over to `destination`.
+// The order matters: changing the owner clears the setuid / setgid bits
+// and file capabilities, while all the others bump the change time.
+pub fn preserve_attributes(
+	source: &Path,
+	destination: &Path,
+	preserve: &Preserve,
+) -> Vec<PreserveError> {
+	let mut errors: Vec<(String, io::Error)> = Vec::new();
+	let meta = match fs::metadata(source) {
+		Ok(meta) => meta,
+		Err(err) => {
+			return vec![PreserveError {
+				path: destination.to_path_buf(),
+				attribute: String::from("attributes"),
+				err,
+			}]
+		}
+	};
+
+	if preserve.ownership {
+		if let Err(err) = copy_ownership(&meta, destination) {
+			if preserve.report_ownership {
+				errors.push((String::from("ownership"), err));
+			}
+		}
+	}
+
+	if preserve.xattr {
+		// Setting user.* attributes requires write access, which read-only files lack.
+		let current = fs::metadata(destination).ok().map(|m| m.permissions());
+		let writable = current.as_ref().and_then(owner_writable);
+		if let Some(writable) = &writable {
+			let _ = fs::set_permissions(destination, writable.clone());
+		}
+		errors.extend(copy_xattrs(source, destination));
+		if let (Some(_), Some(current)) = (writable, current) {
+			let _ = fs::set_permissions(destination, current);
+		}
+	}
+
+	if preserve.mode {
+		if let Err(err) = fs::set_permissions(destination, meta.permissions()) {
+			errors.push((String::from("mode"), err));
+		}
+	}
+
+	if preserve.timestamps {
+		let mut times = FileTimes::new();
+		if let Ok(accessed) = meta.accessed() {
+			times = times.set_accessed(accessed);
+		}
+		if let Ok(modified) = meta.modified() {
+			times = times.set_modified(modified);
+		}
+		let result = open_for_timestamps(destination).and_then(|file| file.set_times(times));
+		if let Err(err) = result {
+			errors.push((String::from("timestamps"), err));
+		}
+	}
+
+	return errors
+		.into_iter()
+		.map(|(attribute, err)| PreserveError {
+			path: destination.to_path_buf(),
+			attribute,
+			err,
+		})
+		.collect();
+}
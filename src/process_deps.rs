@@ -16,27 +16,170 @@
  */
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+#[cfg(unix)]
+use std::os::unix::fs::symlink;
+// Creating symlinks on Windows requires either admin rights or developer mode.
+#[cfg(windows)]
+use std::os::windows::fs::symlink_file as symlink;
+use std::path::{Path, PathBuf};
 
 extern crate same_file;
 use same_file::is_same_file;
 
-use crate::resolver::Status;
+use crate::install_name::{has_install_names, rewrite_install_names, Rewrite};
+use crate::layout::{install_name_rpath_for, runpath_for};
+use crate::parser::DependencyKind;
+use crate::preserve::preserve_attributes;
+use crate::resolver::{leaf_name, Library, Status};
+use crate::set_rpath::{has_runpath, set_runpath};
 use crate::settings::Settings;
+use crate::sha256::sha256_file;
 use crate::version::*;
 
-enum ProcessingStatus {
+pub enum ProcessingStatus {
 	Ignored,
 	ResolveError,
+	Unreadable,
 	Skipped,
 	Failed,
 	Success,
 }
 
+// A file written to the target directory.
+pub struct BundledFile {
+	// Relative to the target directory.
+	pub path: PathBuf,
+	// The file the copy was made from, or the symlink target.
+	pub source: PathBuf,
+	pub symlink: bool,
+}
+
+fn read_metadata(path: &Path) -> Result<fs::Metadata, String> {
+	match fs::metadata(path) {
+		Ok(meta) => return Ok(meta),
+		Err(err) => {
+			return Err(format!(
+				"Failed to query \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			))
+		}
+	}
+}
+
+fn hash_file(path: &Path) -> Result<String, String> {
+	match sha256_file(path) {
+		Ok(hash) => return Ok(hash),
+		Err(err) => {
+			return Err(format!(
+				"Failed to compute checksum of \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			))
+		}
+	}
+}
+
+// Changes made to a file after copying it.
+struct Edits<'a> {
+	runpath: Option<String>,
+	install_names: Option<Rewrite<'a>>,
+}
+
+impl<'a> Edits<'a> {
+	fn new(subdir: &Path, settings: &'a Settings) -> Edits<'a> {
+		let layout = &settings.layout;
+		return Edits {
+			runpath: settings
+				.set_rpath
+				.as_ref()
+				.map(|value| runpath_for(value, subdir, layout)),
+			install_names: settings
+				.install_name_prefix
+				.as_deref()
+				.map(|prefix| Rewrite {
+					prefix,
+					renames: &settings.install_names,
+					rpath: install_name_rpath_for(prefix, subdir, layout),
+				}),
+		};
+	}
+
+	fn any(&self) -> bool {
+		return self.runpath.is_some() || self.install_names.is_some();
+	}
+
+	fn apply(&self, path: &Path) -> Result<(), String> {
+		if let Some(runpath) = &self.runpath {
+			set_runpath(path, runpath).map_err(|err| err.to_string())?;
+		}
+		if let Some(rewrite) = &self.install_names {
+			if rewrite_install_names(path, rewrite).map_err(|err| err.to_string())? {
+				eprintln!(
+					"{}: warning: the code signature of \"{}\" is no longer valid, re-sign it with \"codesign --force --sign -\"",
+					PROGRAM_NAME,
+					path.to_string_lossy()
+				);
+			}
+		}
+		return Ok(());
+	}
+
+	// Check if applying the edits would leave the file unchanged.
+	fn applied(&self, path: &Path) -> bool {
+		if let Some(runpath) = &self.runpath {
+			if !has_runpath(path, runpath) {
+				return false;
+			}
+		}
+		if let Some(rewrite) = &self.install_names {
+			if !has_install_names(path, rewrite) {
+				return false;
+			}
+		}
+		return true;
+	}
+}
+
+// Check if copying the file over again would produce the same result.
+fn is_up_to_date(
+	source: &Path,
+	destination: &Path,
+	relative: &Path,
+	edits: &Edits,
+	settings: &Settings,
+) -> Result<bool, String> {
+	// The copy gets modified after being made, so it has to be compared against what was recorded.
+	if edits.any() {
+		match settings.manifest.get(relative) {
+			Some(entry) => {
+				return Ok(
+					entry.is_copy_of(source, &settings.target_dir) && edits.applied(destination)
+				)
+			}
+			None => return Ok(false),
+		}
+	}
+
+	let src_meta = read_metadata(source)?;
+	let dst_meta = read_metadata(destination)?;
+	if src_meta.len() != dst_meta.len() {
+		return Ok(false);
+	}
+	if let (Ok(src_time), Ok(dst_time)) = (src_meta.modified(), dst_meta.modified()) {
+		if src_time == dst_time {
+			return Ok(true);
+		}
+	}
+	return Ok(hash_file(source)? == hash_file(destination)?);
+}
+
 fn should_copy(
 	name: &String,
 	source: &PathBuf,
 	destination: &PathBuf,
+	relative: &Path,
+	edits: &Edits,
 	settings: &Settings,
 ) -> Result<bool, String> {
 	if !destination.exists() {
@@ -53,7 +196,7 @@ fn should_copy(
 		return Ok(false);
 	}
 
-	match is_same_file(source, &destination) {
+	match is_same_file(source, destination) {
 		Ok(true) => {
 			if settings.verbose {
 				println!(
@@ -71,86 +214,394 @@ fn should_copy(
 				err
 			));
 		}
+		Ok(false) => { /* carry on */ }
+	};
+
+	if settings.update && is_up_to_date(source, destination, relative, edits, settings)? {
+		if settings.verbose {
+			println!("\"{}\": up to date in target directory", name);
+		}
+		return Ok(false);
+	}
+	return Ok(true);
+}
+
+// Guard against symlink loops.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+// The chain of symlinks leading from the resolved path to the actual file,
+// e.g. "libfoo.so.1" -> "libfoo.so.1.2" -> "libfoo.so.1.2.3".
+struct SymlinkChain {
+	// All the paths visited, starting with the resolved path and ending with the real file.
+	hops: Vec<PathBuf>,
+	// Names to create in the target directory, one per hop (minus duplicates).
+	// The first one is always the name the library was requested by.
+	names: Vec<String>,
+}
+
+impl SymlinkChain {
+	fn new(name: &str, resolved: &Path) -> Result<SymlinkChain, String> {
+		let mut hops = vec![resolved.to_path_buf()];
+		let mut names = vec![String::from(name)];
+
+		let mut current = resolved.to_path_buf();
+		while let Ok(target) = fs::read_link(&current) {
+			if hops.len() > MAX_SYMLINK_HOPS {
+				return Err(format!(
+					"Too many levels of symbolic links while following \"{}\"",
+					resolved.to_string_lossy()
+				));
+			}
+
+			current = match current.parent() {
+				Some(parent) => parent.join(target),
+				None => target,
+			};
+			if let Some(file_name) = current.file_name() {
+				let file_name = file_name.to_string_lossy().into_owned();
+				if !names.contains(&file_name) {
+					names.push(file_name);
+				}
+			}
+			hops.push(current.clone());
+		}
+
+		return Ok(SymlinkChain { hops, names });
+	}
+
+	fn real_file(&self) -> &PathBuf {
+		return self.hops.last().unwrap();
+	}
+}
+
+// Copy a file into `subdir` of the target directory.
+fn copy_file(
+	name: &String,
+	source: &PathBuf,
+	subdir: &Path,
+	copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus {
+	let dir = settings.target_dir.join(subdir);
+	if let Err(err) = fs::create_dir_all(&dir) {
+		eprintln!(
+			"{}: failed to create directory \"{}\": {}",
+			PROGRAM_NAME,
+			dir.to_string_lossy(),
+			err
+		);
+		return ProcessingStatus::Failed;
+	}
+	let destination = dir.join(name);
+	let relative = subdir.join(name);
+	let edits = Edits::new(subdir, settings);
+
+	match should_copy(name, source, &destination, &relative, &edits, settings) {
+		Err(err) => {
+			eprintln!("{}: {}", PROGRAM_NAME, err);
+			return ProcessingStatus::Failed;
+		}
 		Ok(false) => {
-			return Ok(true);
+			return ProcessingStatus::Skipped;
+		}
+		Ok(true) => match fs::copy(source, &destination) {
+			Ok(_) => {
+				if settings.verbose {
+					println!(
+						"\"{}\": {} -> {}",
+						name,
+						source.to_string_lossy(),
+						destination.to_string_lossy()
+					)
+				}
+				// Only ever modify the freshly made copy, never a file that was already there.
+				if let Err(err) = edits.apply(&destination) {
+					eprintln!("{}: {}", PROGRAM_NAME, err);
+					return ProcessingStatus::Failed;
+				}
+				// Done last, as modifying the file would bump its timestamps.
+				if settings.preserve.any() {
+					for err in preserve_attributes(source, &destination, &settings.preserve) {
+						eprintln!("{}: warning: {}", PROGRAM_NAME, err);
+					}
+				}
+				copied.push(BundledFile {
+					path: relative,
+					source: source.clone(),
+					symlink: false,
+				});
+				return ProcessingStatus::Success;
+			}
+			Err(err) => {
+				eprintln!("{}: failed to copy \"{}\": {}", PROGRAM_NAME, name, err);
+				return ProcessingStatus::Failed;
+			}
+		},
+	}
+}
+
+fn create_symlink(
+	name: &String,
+	target: &String,
+	copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus {
+	let destination = settings.lib_target_dir().join(name);
+
+	if let Ok(existing) = fs::read_link(&destination) {
+		if existing == Path::new(target) {
+			if settings.verbose {
+				println!("\"{}\": symlink already present in target directory", name);
+			}
+			return ProcessingStatus::Skipped;
+		}
+	}
+
+	// Use symlink_metadata() rather than exists(), as the latter returns false for dangling symlinks.
+	if fs::symlink_metadata(&destination).is_ok() {
+		if settings.no_clobber {
+			if settings.verbose {
+				println!(
+					"\"{}\": already exists in the target directory and --no-clobber was specified",
+					name
+				);
+			}
+			return ProcessingStatus::Skipped;
+		}
+		if let Err(err) = fs::remove_file(&destination) {
+			eprintln!(
+				"{}: failed to remove \"{}\": {}",
+				PROGRAM_NAME,
+				destination.to_string_lossy(),
+				err
+			);
+			return ProcessingStatus::Failed;
+		}
+	}
+
+	match symlink(target, &destination) {
+		Ok(_) => {
+			if settings.verbose {
+				println!(
+					"\"{}\": {} -> {} (symlink)",
+					name,
+					destination.to_string_lossy(),
+					target
+				);
+			}
+			copied.push(BundledFile {
+				path: settings.layout.lib_dir.join(name),
+				source: PathBuf::from(target),
+				symlink: true,
+			});
+			return ProcessingStatus::Success;
+		}
+		Err(err) => {
+			eprintln!(
+				"{}: failed to create symlink \"{}\": {}",
+				PROGRAM_NAME,
+				destination.to_string_lossy(),
+				err
+			);
+			return ProcessingStatus::Failed;
+		}
+	}
+}
+
+// Copy the real file and then recreate the symlinks pointing to it, innermost first.
+fn copy_chain(
+	name: &str,
+	resolved: &Path,
+	copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus {
+	let chain = match SymlinkChain::new(name, resolved) {
+		Ok(chain) => chain,
+		Err(err) => {
+			eprintln!("{}: {}", PROGRAM_NAME, err);
+			return ProcessingStatus::Failed;
 		}
 	};
+
+	let real_name = chain.names.last().unwrap();
+	let mut status = copy_file(
+		real_name,
+		chain.real_file(),
+		&settings.layout.lib_dir,
+		copied,
+		settings,
+	);
+	if let ProcessingStatus::Failed = status {
+		return status;
+	}
+
+	for pair in chain.names.windows(2).rev() {
+		match create_symlink(&pair[0], &pair[1], copied, settings) {
+			ProcessingStatus::Failed => return ProcessingStatus::Failed,
+			ProcessingStatus::Success => status = ProcessingStatus::Success,
+			_ => {}
+		}
+	}
+	return status;
 }
 
-fn dep_copy(name: &String, status: &Status, settings: &Settings) -> ProcessingStatus {
-	match status {
-		Status::Ignored => {
+// The loader carries on without a weak dependency, so failing to find one is not an error.
+fn unresolved_status(library: &Library) -> ProcessingStatus {
+	match library.kind {
+		DependencyKind::Weak => return ProcessingStatus::Skipped,
+		_ => return ProcessingStatus::ResolveError,
+	}
+}
+
+fn dep_copy(
+	name: &String,
+	library: &Library,
+	copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus {
+	match &library.status {
+		Status::Ignored(_) => {
 			if settings.verbose {
 				println!("\"{}\": ignored, skipping", name)
 			}
 			return ProcessingStatus::Ignored;
 		}
 		Status::FailedToResolve => {
-			eprintln!("{}: failed to resolve \"{}\"", PROGRAM_NAME, name);
-			return ProcessingStatus::ResolveError;
+			match library.kind {
+				DependencyKind::Weak => eprintln!(
+					"{}: warning: failed to resolve weak dependency \"{}\", skipping",
+					PROGRAM_NAME, name
+				),
+				_ => eprintln!("{}: failed to resolve \"{}\"", PROGRAM_NAME, name),
+			}
+			return unresolved_status(library);
 		}
-		Status::Resolved(resolved) => {
-			let mut destination = settings.target_dir.clone();
-			destination.push(name);
-
-			match should_copy(name, resolved, &destination, settings) {
-				Err(err) => {
-					eprintln!("{}: {}", PROGRAM_NAME, err);
-					return ProcessingStatus::Failed;
-				}
-				Ok(false) => {
-					return ProcessingStatus::Skipped;
-				}
-				Ok(true) => match fs::copy(resolved, &destination) {
-					Ok(_) => {
-						if settings.verbose {
-							println!(
-								"\"{}\": {} -> {}",
-								name,
-								resolved.to_string_lossy(),
-								destination.to_string_lossy()
-							)
-						}
-						return ProcessingStatus::Success;
-					}
-					Err(err) => {
-						eprintln!("{}: failed to copy \"{}\": {}", PROGRAM_NAME, name, err);
-						return ProcessingStatus::Failed;
-					}
-				},
+		// Mach-O libraries are requested by their install names (e.g. "@rpath/libfoo.dylib"),
+		// but copied over under just the file name.
+		Status::Resolved(resolved, _) => match settings.symlinks {
+			true => return copy_chain(&leaf_name(name), resolved, copied, settings),
+			false => {
+				return copy_file(
+					&leaf_name(name),
+					resolved,
+					&settings.layout.lib_dir,
+					copied,
+					settings,
+				)
+			}
+		},
+		Status::Unreadable(_, _, err) => {
+			eprintln!("{}: {}", PROGRAM_NAME, err);
+			return ProcessingStatus::Unreadable;
+		}
+		Status::LinkerScript(_, _) => {
+			if settings.verbose {
+				println!(
+					"\"{}\": linker script, copying the libraries it lists instead",
+					name
+				);
 			}
+			return ProcessingStatus::Skipped;
+		}
+		Status::ApiSet(host) => {
+			if settings.verbose {
+				println!("\"{}\": API set, copying {} instead", name, host);
+			}
+			return ProcessingStatus::Skipped;
 		}
 	}
 }
 
-fn dep_print(name: &String, status: &Status, _settings: &Settings) -> ProcessingStatus {
-	match status {
-		Status::Ignored => {
-			println!("\"{}\": (ignored)", name);
+fn dep_print(
+	name: &String,
+	library: &Library,
+	_copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus {
+	let kind = match library.kind {
+		DependencyKind::Normal => "",
+		DependencyKind::DelayLoad => " (delay-load)",
+		DependencyKind::Weak => " (weak)",
+	};
+	match &library.status {
+		Status::Ignored(_) => {
+			println!("\"{}\": (ignored){}", name, kind);
 			return ProcessingStatus::Ignored;
 		}
 		Status::FailedToResolve => {
-			println!("\"{}\": (failed to resolve)", name);
-			return ProcessingStatus::ResolveError;
+			println!("\"{}\": (failed to resolve){}", name, kind);
+			return unresolved_status(library);
+		}
+		Status::Resolved(r, _) => {
+			let mut hops = vec![r.clone()];
+			if settings.symlinks {
+				match SymlinkChain::new(&leaf_name(name), r) {
+					Ok(chain) => hops = chain.hops,
+					Err(err) => eprintln!("{}: {}", PROGRAM_NAME, err),
+				}
+			}
+
+			let hops: Vec<_> = hops.iter().map(|hop| hop.to_string_lossy()).collect();
+			println!("\"{}\": {}{}", name, hops.join(" -> "), kind);
+			return ProcessingStatus::Success;
 		}
-		Status::Resolved(r) => {
-			println!("\"{}\": {}", name, r.to_string_lossy());
+		Status::LinkerScript(r, _) => {
+			println!(
+				"\"{}\": {} (linker script){}",
+				name,
+				r.to_string_lossy(),
+				kind
+			);
+			return ProcessingStatus::Success;
+		}
+		Status::ApiSet(host) => {
+			println!("\"{}\": {} (API set){}", name, host, kind);
 			return ProcessingStatus::Success;
 		}
+		Status::Unreadable(r, _, err) => {
+			println!("\"{}\": {} (unreadable){}", name, r.to_string_lossy(), kind);
+			eprintln!("{}: {}", PROGRAM_NAME, err);
+			return ProcessingStatus::Unreadable;
+		}
+	}
+}
+
+// Like dep_print(), but without printing anything.
+fn dep_check(
+	_name: &String,
+	library: &Library,
+	_copied: &mut Vec<BundledFile>,
+	_settings: &Settings,
+) -> ProcessingStatus {
+	match &library.status {
+		Status::Ignored(_) => return ProcessingStatus::Ignored,
+		Status::FailedToResolve => return unresolved_status(library),
+		Status::Resolved(_, _) => return ProcessingStatus::Success,
+		Status::Unreadable(_, _, _) => return ProcessingStatus::Unreadable,
+		Status::LinkerScript(_, _) => return ProcessingStatus::Success,
+		Status::ApiSet(_) => return ProcessingStatus::Success,
 	}
 }
 
-type DepCallback = fn(name: &String, status: &Status, settings: &Settings) -> ProcessingStatus;
+type DepCallback = fn(
+	name: &String,
+	library: &Library,
+	copied: &mut Vec<BundledFile>,
+	settings: &Settings,
+) -> ProcessingStatus;
 
 fn process_deps(
-	deps: &HashMap<String, Status>,
+	deps: &HashMap<String, Library>,
 	callback: DepCallback,
 	settings: &Settings,
 ) -> ProcessingResult {
 	let mut result = ProcessingResult {
 		failed_to_resolve: 0,
 		failed_to_copy: 0,
+		unreadable: 0,
 		successful: 0,
+		outcomes: Vec::with_capacity(deps.len()),
+		copied: Vec::new(),
 	};
 
 	let mut sorted_keys = deps.keys().collect::<Vec<&String>>();
@@ -158,11 +609,14 @@ fn process_deps(
 
 	for key in sorted_keys {
 		let val = deps.get(key.as_str()).unwrap();
-		match callback(&key, val, settings) {
+		let status = callback(key, val, &mut result.copied, settings);
+		match status {
 			ProcessingStatus::ResolveError => result.failed_to_resolve += 1,
 			ProcessingStatus::Failed => result.failed_to_copy += 1,
+			ProcessingStatus::Unreadable => result.unreadable += 1,
 			_ => result.successful += 1,
 		}
+		result.outcomes.push((key.clone(), status));
 	}
 
 	return result;
@@ -171,13 +625,66 @@ fn process_deps(
 pub struct ProcessingResult {
 	pub failed_to_resolve: i32,
 	pub failed_to_copy: i32,
+	pub unreadable: i32,
 	pub successful: i32,
+	// Per-dependency outcome, sorted by name.
+	pub outcomes: Vec<(String, ProcessingStatus)>,
+	// Files written to the target directory.
+	pub copied: Vec<BundledFile>,
 }
 
-pub fn copy_deps(deps: &HashMap<String, Status>, settings: &Settings) -> ProcessingResult {
+pub fn copy_deps(deps: &HashMap<String, Library>, settings: &Settings) -> ProcessingResult {
 	return process_deps(deps, dep_copy, settings);
 }
 
-pub fn print_deps(deps: &HashMap<String, Status>, settings: &Settings) -> ProcessingResult {
+// With a bundle layout, the executable is copied along with its dependencies.
+pub fn copy_executable(result: &mut ProcessingResult, settings: &Settings) {
+	let bin_dir = match &settings.layout.bin_dir {
+		Some(bin_dir) => bin_dir,
+		None => return,
+	};
+
+	let name = settings.executable_name();
+	match copy_file(
+		&name,
+		&settings.executable,
+		bin_dir,
+		&mut result.copied,
+		settings,
+	) {
+		ProcessingStatus::Failed => result.failed_to_copy += 1,
+		ProcessingStatus::Success => result.successful += 1,
+		_ => { /* already there */ }
+	}
+}
+
+// All the paths (relative to the target directory) that make up the bundle,
+// whether they need to be copied or not.
+pub fn bundle_paths(deps: &HashMap<String, Library>, settings: &Settings) -> Vec<PathBuf> {
+	let lib_dir = &settings.layout.lib_dir;
+
+	let mut paths = Vec::with_capacity(deps.len() + 1);
+	for (name, library) in deps {
+		let resolved = match &library.status {
+			Status::Resolved(resolved, _) => resolved,
+			_ => continue,
+		};
+		let name = leaf_name(name);
+		match (settings.symlinks, SymlinkChain::new(&name, resolved)) {
+			(true, Ok(chain)) => paths.extend(chain.names.iter().map(|n| lib_dir.join(n))),
+			_ => paths.push(lib_dir.join(name)),
+		}
+	}
+	if let Some(bin_dir) = &settings.layout.bin_dir {
+		paths.push(bin_dir.join(settings.executable_name()));
+	}
+	return paths;
+}
+
+pub fn check_deps(deps: &HashMap<String, Library>, settings: &Settings) -> ProcessingResult {
+	return process_deps(deps, dep_check, settings);
+}
+
+pub fn print_deps(deps: &HashMap<String, Library>, settings: &Settings) -> ProcessingResult {
 	return process_deps(deps, dep_print, settings);
 }
@@ -14,30 +14,133 @@
  * You should have received a copy of the GNU General Public License along with
  * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
  */
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
+use std::rc::Rc;
+
+extern crate goblin;
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_ARM, EM_RISCV, EM_X86_64};
 
 extern crate regex;
 use regex::RegexSet;
 use regex::RegexSetBuilder;
 
 use crate::{
-	parser::{get_deps, GetDepsError, Object, ObjectType},
+	api_set::{find_api_set, ApiSetPolicy},
+	ld_so_conf::with_sysroot,
+	linker_script::{read_linker_script, ScriptInput},
+	parser::{
+		expand_loader_path, get_archs, get_deps, get_deps_matching, Arch, Dependency,
+		DependencyKind, GetDepsError, Object, ObjectType, EM_LOONGARCH,
+	},
 	settings::Settings,
+	windows_dlls::{is_known_dll, is_system_dll},
 };
 
+// Where a library was found.
+#[derive(Clone, Copy)]
+pub enum SearchTier {
+	ExeDir,
+	AppDir,
+	SearchDir,
+	Rpath,
+	LdLibraryPath,
+	Runpath,
+	LdCache,
+	System,
+	SonameIndex,
+	LinkerScript,
+	LoaderPath,
+	ExecutablePath,
+	InstallName,
+	DotLocal,
+	Assembly,
+}
+
+impl SearchTier {
+	pub fn name(&self) -> &'static str {
+		match self {
+			SearchTier::ExeDir => return "exedir",
+			SearchTier::AppDir => return "app-dir",
+			SearchTier::SearchDir => return "search-dir",
+			SearchTier::Rpath => return "rpath",
+			SearchTier::LdLibraryPath => return "ld-library-path",
+			SearchTier::Runpath => return "runpath",
+			SearchTier::LdCache => return "ld-cache",
+			SearchTier::System => return "system",
+			SearchTier::SonameIndex => return "soname-index",
+			SearchTier::LinkerScript => return "linker-script",
+			SearchTier::LoaderPath => return "loader-path",
+			SearchTier::ExecutablePath => return "executable-path",
+			SearchTier::InstallName => return "install-name",
+			SearchTier::DotLocal => return "dot-local",
+			SearchTier::Assembly => return "assembly",
+		}
+	}
+
+	pub fn describe(&self) -> &'static str {
+		match self {
+			SearchTier::ExeDir => return "the executable's directory (--exedir)",
+			SearchTier::AppDir => return "the application directory",
+			SearchTier::SearchDir => return "a user-specified directory (--search-dir)",
+			SearchTier::Rpath => return "the requester's RPATH",
+			SearchTier::LdLibraryPath => return "LD_LIBRARY_PATH",
+			SearchTier::Runpath => return "the requester's RUNPATH",
+			SearchTier::LdCache => return "the ld.so cache",
+			SearchTier::System => return "the system directories",
+			SearchTier::SonameIndex => return "SONAME lookup (--search-dir / --exedir)",
+			SearchTier::LinkerScript => return "a path listed in a linker script",
+			SearchTier::LoaderPath => return "a path relative to the requester (@loader_path)",
+			SearchTier::ExecutablePath => {
+				return "a path relative to the executable (@executable_path)"
+			}
+			SearchTier::InstallName => return "the path in the install name",
+			SearchTier::DotLocal => return "the executable's .local directory (DLL redirection)",
+			SearchTier::Assembly => return "a private assembly listed in the application manifest",
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+pub enum IgnoreReason {
+	// Matched one of the patterns passed via --ignore.
+	IgnoreList,
+	// Matched the built-in list of system libraries.
+	BuiltinList,
+	// A Windows API set, provided by the OS.
+	ApiSet,
+}
+
+impl IgnoreReason {
+	pub fn describe(&self) -> &'static str {
+		match self {
+			IgnoreReason::IgnoreList => return "matches --ignore",
+			IgnoreReason::BuiltinList => return "on the built-in ignore list",
+			IgnoreReason::ApiSet => return "an API set provided by Windows",
+		}
+	}
+}
+
 pub enum Status {
-	Ignored,
+	Ignored(IgnoreReason),
 	FailedToResolve,
-	Resolved(PathBuf),
+	Resolved(PathBuf, SearchTier),
+	// The library was found, but reading its list of dependencies failed.
+	Unreadable(PathBuf, SearchTier, GetDepsError),
+	// The name resolved to a GNU ld linker script. The libraries it lists
+	// are processed in its stead; the script itself is not copied.
+	LinkerScript(PathBuf, SearchTier),
+	// A Windows API set, to be replaced by the named host DLL.
+	ApiSet(String),
 }
 
-fn find_in_directory(name: &String, type_: &ObjectType, dir: &Path) -> Option<String> {
+pub fn find_in_directory(name: &String, type_: &ObjectType, dir: &Path) -> Option<String> {
 	match type_ {
-		// With ELF, look for an exact match.
-		ObjectType::Elf32 | ObjectType::Elf64 => {
+		// With ELF and Mach-O, look for an exact match.
+		ObjectType::Elf32 | ObjectType::Elf64 | ObjectType::MachO32 | ObjectType::MachO64 => {
 			let mut filepath = PathBuf::from(dir);
 			filepath.push(name);
 
@@ -48,15 +151,10 @@ fn find_in_directory(name: &String, type_: &ObjectType, dir: &Path) -> Option<St
 		// With PE, iterate over the directory entries and look for a case-insensitive match.
 		ObjectType::Exe32 | ObjectType::Exe64 => {
 			if let Ok(entries) = fs::read_dir(dir) {
-				for entry in entries {
-					if let Ok(entry) = entry {
-						match entry.file_name().to_str() {
-							Some(entry_name) => {
-								if name.eq_ignore_ascii_case(entry_name) {
-									return Some(String::from(entry_name));
-								}
-							}
-							None => { /* ignore */ }
+				for entry in entries.flatten() {
+					if let Some(entry_name) = entry.file_name().to_str() {
+						if name.eq_ignore_ascii_case(entry_name) {
+							return Some(String::from(entry_name));
 						}
 					}
 				}
@@ -68,127 +166,1012 @@ fn find_in_directory(name: &String, type_: &ObjectType, dir: &Path) -> Option<St
 }
 
 lazy_static! {
-	static ref IGNORELIST_ELF32: RegexSet = RegexSetBuilder::new(vec![r"ld-linux\.so*"])
+	static ref IGNORELIST_ELF_I386: RegexSet = RegexSetBuilder::new(vec![r"ld-linux\.so*"])
 		.build()
 		.unwrap();
-	static ref IGNORELIST_ELF64: RegexSet = RegexSetBuilder::new(vec![r"ld-linux-x86-64\.so*"])
+	static ref IGNORELIST_ELF_X86_64: RegexSet = RegexSetBuilder::new(vec![r"ld-linux-x86-64\.so*"])
 		.build()
 		.unwrap();
-	static ref IGNORELIST_EXE: RegexSet = RegexSetBuilder::new(vec![
-		r"^ADVAPI32\.dll$",
-		r"^COMCTL32\.dll$",
-		r"^COMDLG32\.dll$",
-		r"^CRYPT32\.dll$",
-		r"^GDI32\.dll$",
-		r"^IMM32\.dll$",
-		r"^KERNEL32\.dll$",
-		r"^msvcrt\.dll$",
-		r"^ncrypt\.dll$",
-		r"^NETAPI32\.dll$",
-		r"^NTDLL\.dll$",
-		r"^ole32\.dll$",
-		r"^OLEAUT32\.dll$",
-		r"^Secur32\.dll$",
-		r"^SETUPAPI\.dll$",
-		r"^SHSCRAP\.dll$",
-		r"^SHELL32\.dll$",
-		r"^USER32\.dll$",
-		r"^UserEnv\.dll$",
-		r"^VERSION\.dll$",
-		r"^WINMM\.dll$",
-		r"^WLDAP32\.dll$",
-		r"^WS2_32\.dll$",
-	])
-	.case_insensitive(true)
-	.build()
-	.unwrap();
-}
-
-fn exists_in_ignore_list(name: &String, type_: &ObjectType, settings: &Settings) -> bool {
+	static ref IGNORELIST_ELF_X32: RegexSet = RegexSetBuilder::new(vec![r"ld-linux-x32\.so*"])
+		.build()
+		.unwrap();
+	static ref IGNORELIST_ELF_AARCH64: RegexSet =
+		RegexSetBuilder::new(vec![r"ld-linux-aarch64(_be)?\.so*"])
+			.build()
+			.unwrap();
+	static ref IGNORELIST_ELF_ARM: RegexSet = RegexSetBuilder::new(vec![r"ld-linux(-armhf)?\.so*"])
+		.build()
+		.unwrap();
+	static ref IGNORELIST_ELF_RISCV: RegexSet =
+		RegexSetBuilder::new(vec![r"ld-linux-riscv(32|64)-[a-z0-9]+\.so*"])
+			.build()
+			.unwrap();
+	static ref IGNORELIST_ELF_LOONGARCH: RegexSet =
+		RegexSetBuilder::new(vec![r"ld-linux-loongarch-[a-z0-9]+\.so*"])
+			.build()
+			.unwrap();
+	// PowerPC, MIPS, s390x, m68k and SPARC use plain "ld.so.1", "ld64.so.1" and the like.
+	static ref IGNORELIST_ELF_OTHER: RegexSet =
+		RegexSetBuilder::new(vec![r"^ld(64)?\.so\.[0-9]+$", r"ld-linux\.so*"])
+			.build()
+			.unwrap();
+	// Everything under these paths ships with the OS (and mostly lives only in the dyld shared cache).
+	static ref IGNORELIST_MACHO: RegexSet =
+		RegexSetBuilder::new(vec![r"^/usr/lib/", r"^/System/Library/"])
+			.build()
+			.unwrap();
+}
+
+fn exists_in_ignore_list(
+	name: &str,
+	type_: &ObjectType,
+	arch: &Arch,
+	settings: &Settings,
+) -> Option<IgnoreReason> {
 	if settings.ignore_list.is_match(name) {
-		return true;
+		return Some(IgnoreReason::IgnoreList);
 	}
 
 	let builtin_ignore_list: &RegexSet = match type_ {
-		ObjectType::Elf32 => &IGNORELIST_ELF32,
-		ObjectType::Elf64 => &IGNORELIST_ELF64,
-		ObjectType::Exe32 | ObjectType::Exe64 => &IGNORELIST_EXE,
+		ObjectType::Exe32 | ObjectType::Exe64 => {
+			match is_system_dll(name, settings.windows_target) {
+				true => return Some(IgnoreReason::BuiltinList),
+				false => return None,
+			}
+		}
+		ObjectType::Elf32 | ObjectType::Elf64 => match (arch.machine, type_.is_64()) {
+			(EM_386, _) => &IGNORELIST_ELF_I386,
+			(EM_X86_64, true) => &IGNORELIST_ELF_X86_64,
+			(EM_X86_64, false) => &IGNORELIST_ELF_X32,
+			(EM_AARCH64, _) => &IGNORELIST_ELF_AARCH64,
+			(EM_ARM, _) => &IGNORELIST_ELF_ARM,
+			(EM_RISCV, _) => &IGNORELIST_ELF_RISCV,
+			(EM_LOONGARCH, _) => &IGNORELIST_ELF_LOONGARCH,
+			_ => &IGNORELIST_ELF_OTHER,
+		},
+		ObjectType::MachO32 | ObjectType::MachO64 => &IGNORELIST_MACHO,
+	};
+
+	match builtin_ignore_list.is_match(name) {
+		true => return Some(IgnoreReason::BuiltinList),
+		false => return None,
+	}
+}
+
+// Check the library name against the ignore and override lists.
+// Returns the reason for ignoring the library, if any,
+// and whether the override list prevented the library from being ignored.
+fn check_lists(
+	name: &str,
+	type_: &ObjectType,
+	arch: &Arch,
+	settings: &Settings,
+) -> (Option<IgnoreReason>, bool) {
+	let reason = exists_in_ignore_list(name, type_, arch, settings);
+	match (reason, settings.override_list.is_match(name)) {
+		(Some(_), true) => return (None, true),
+		(reason, _) => return (reason, false),
+	}
+}
+
+// Debian and derivatives keep libraries in per-architecture directories.
+// Cross-compilation toolchains install target libraries into /usr/TRIPLET/lib/.
+pub fn multiarch_dirs(triplet: &str, sysroot: Option<&Path>) -> Vec<PathBuf> {
+	return [
+		format!("/lib/{}/", triplet),
+		format!("/usr/lib/{}/", triplet),
+		format!("/usr/local/lib/{}/", triplet),
+		format!("/usr/{}/lib/", triplet),
+	]
+	.iter()
+	.map(|dir| with_sysroot(sysroot, Path::new(dir)))
+	.collect();
+}
+
+fn describe_class(type_: &ObjectType) -> &'static str {
+	match type_.is_64() {
+		true => return "64-bit",
+		false => return "32-bit",
+	}
+}
+
+fn describe_endianness(arch: &Arch) -> &'static str {
+	match arch.little_endian {
+		true => return "little-endian",
+		false => return "big-endian",
+	}
+}
+
+fn describe_float_abi(arch: &Arch) -> &'static str {
+	match arch.is_arm_hard_float() {
+		true => return "hard-float",
+		false => return "soft-float",
+	}
+}
+
+fn describe_format(type_: &ObjectType) -> &'static str {
+	match type_ {
+		ObjectType::Elf32 | ObjectType::Elf64 => return "an ELF",
+		ObjectType::Exe32 | ObjectType::Exe64 => return "a PE",
+		ObjectType::MachO32 | ObjectType::MachO64 => return "a Mach-O",
+	}
+}
+
+// Check if the library at given path can be loaded by an object of given type and architecture.
+// Like the real loaders, candidates that do not match are skipped over.
+// Universal binaries are accepted if any of the architectures they contain matches.
+fn check_compatibility(path: &Path, type_: &ObjectType, arch: &Arch) -> Result<(), String> {
+	let candidates = match get_archs(path) {
+		Ok(value) => value,
+		Err(e) => return Err(e.to_string()),
+	};
+
+	let mut first_error = None;
+	for (lib_type, lib_arch) in candidates {
+		match compare_arch(&lib_type, &lib_arch, type_, arch) {
+			Ok(_) => return Ok(()),
+			Err(reason) => {
+				first_error.get_or_insert(reason);
+			}
+		}
+	}
+	return Err(first_error.unwrap_or_else(|| String::from("contains no code")));
+}
+
+fn compare_arch(
+	lib_type: &ObjectType,
+	lib_arch: &Arch,
+	type_: &ObjectType,
+	arch: &Arch,
+) -> Result<(), String> {
+	if lib_type.format() != type_.format() {
+		return Err(format!(
+			"is {} object, expected {}",
+			describe_format(lib_type),
+			type_.format()
+		));
+	}
+	// For PE, the machine type alone determines the bitness.
+	if !type_.is_exe() && lib_type.is_64() != type_.is_64() {
+		return Err(format!(
+			"is a {} object, expected {}",
+			describe_class(lib_type),
+			describe_class(type_)
+		));
+	}
+	if lib_arch.machine != arch.machine {
+		return Err(format!(
+			"is built for {}, expected {}",
+			lib_arch.name(lib_type),
+			arch.name(type_)
+		));
+	}
+	if lib_arch.little_endian != arch.little_endian {
+		return Err(format!(
+			"is {}, expected {}",
+			describe_endianness(lib_arch),
+			describe_endianness(arch)
+		));
+	}
+	if lib_arch.is_arm_hard_float() != arch.is_arm_hard_float() {
+		return Err(format!(
+			"uses the {} ABI, expected {}",
+			describe_float_abi(lib_arch),
+			describe_float_abi(arch)
+		));
+	}
+
+	return Ok(());
+}
+
+// Objects parsed while checking the SONAME of candidates, kept around
+// so that the one picked does not need to be parsed again to read its dependencies.
+#[derive(Default)]
+pub struct ObjectCache {
+	objects: RefCell<HashMap<PathBuf, Object>>,
+}
+
+impl ObjectCache {
+	fn soname(&self, path: &Path) -> Result<Option<String>, GetDepsError> {
+		if let Some(obj) = self.objects.borrow().get(path) {
+			return Ok(obj.soname.clone());
+		}
+		let obj = get_deps(path)?;
+		let soname = obj.soname.clone();
+		self.objects.borrow_mut().insert(path.to_path_buf(), obj);
+		return Ok(soname);
+	}
+
+	fn get_deps_matching(
+		&self,
+		path: &Path,
+		type_: &ObjectType,
+		arch: &Arch,
+	) -> Result<Object, GetDepsError> {
+		match self.objects.borrow_mut().remove(path) {
+			Some(obj) => return Ok(obj),
+			None => return get_deps_matching(path, type_, arch),
+		}
+	}
+}
+
+// ELF libraries are supposed to be found under their SONAME.
+// Libraries that do not specify a SONAME are accepted under any name.
+fn check_soname(path: &Path, name: &str, cache: &ObjectCache) -> Result<(), String> {
+	let soname = match cache.soname(path) {
+		Ok(soname) => soname,
+		// The header looked fine, so this is most likely the right library, just damaged.
+		// Accept it and let resolve_recursively() report it as unreadable.
+		Err(_) => return Ok(()),
 	};
+	match soname {
+		Some(soname) if soname != name => {
+			return Err(format!("has SONAME \"{}\", expected \"{}\"", soname, name));
+		}
+		_ => return Ok(()),
+	}
+}
 
-	return builtin_ignore_list.is_match(name);
+// Everything needed to look up a single library name.
+struct Lookup<'a> {
+	name: &'a String,
+	type_: &'a ObjectType,
+	arch: &'a Arch,
+	settings: &'a Settings,
+	// Whether the SONAME of candidate files should match the name being looked up.
+	verify_soname: bool,
+	cache: &'a ObjectCache,
 }
 
-pub fn resolve(name: &String, type_: &ObjectType, settings: &Settings) -> Status {
-	if !settings.override_list.is_match(name) {
-		if exists_in_ignore_list(name, type_, settings) {
-			return Status::Ignored;
+impl<'a> Lookup<'a> {
+	fn accept(&self, path: &Path) -> bool {
+		let mut check = check_compatibility(path, self.type_, self.arch);
+		if check.is_ok() && self.type_.is_elf() && self.verify_soname {
+			check = check_soname(path, self.name, self.cache);
+		}
+		// Linker scripts can stand in for ELF libraries. Their contents are checked later.
+		if check.is_err() && self.type_.is_elf() && read_linker_script(path).is_some() {
+			check = Ok(());
+		}
+		match check {
+			Ok(_) => return true,
+			Err(reason) => {
+				if self.settings.verbose {
+					println!(
+						"\"{}\": skipping \"{}\": {}",
+						self.name,
+						path.to_string_lossy(),
+						reason
+					);
+				}
+				return false;
+			}
 		}
 	}
 
-	for dir in &settings.search_dirs {
-		match find_in_directory(&name, &type_, dir.as_path()) {
-			Some(resolved) => {
+	fn find_in_directories<'b, I>(&self, dirs: I) -> Option<PathBuf>
+	where
+		I: IntoIterator<Item = &'b PathBuf>,
+	{
+		for dir in dirs {
+			if let Some(resolved) = find_in_directory(self.name, self.type_, dir.as_path()) {
 				let mut path = dir.clone();
 				path.push(resolved);
-				return Status::Resolved(path);
+				if self.accept(&path) {
+					return Some(path);
+				}
 			}
-			None => { /* do nothing */ }
 		}
+		return None;
 	}
+}
+
+// Library search paths inherited from the object that requested the dependency.
+pub struct Requester {
+	// Name of the requesting object.
+	pub name: String,
+	// DT_RPATH entries of the requesting object and all of its loaders.
+	// Objects that have a DT_RUNPATH do not contribute their DT_RPATH to the chain.
+	pub rpath: Vec<PathBuf>,
+	// DT_RUNPATH entries of the requesting object. These are not inherited.
+	pub runpath: Vec<PathBuf>,
+	// Linker scripts refer to libraries by their link-time names
+	// (e.g. "libfoo.so" for "-lfoo"), which need not match the SONAME.
+	pub linker_script: bool,
+	// Directory of the requesting object, used for expanding @loader_path.
+	pub loader_dir: PathBuf,
+}
 
-	let search_paths = match type_ {
-		ObjectType::Elf32 => vec!["/lib/", "/usr/lib/", "/usr/local/lib/"],
-		ObjectType::Elf64 => vec!["/lib64/", "/usr/lib64/", "/usr/local/lib64/"],
-		ObjectType::Exe32 => vec![
-			"/usr/i686-w64-mingw32/sys-root/mingw/bin/", // Fedora
-			"/usr/i686-w64-mingw32/lib/",                // Debian
-		],
-		ObjectType::Exe64 => vec![
-			"/usr/x86_64-w64-mingw32/sys-root/mingw/bin/", // Fedora
-			"/usr/x86_64-w64-mingw32/lib/",                // Debian
-		],
+impl Requester {
+	// Compute the search paths used when resolving the dependencies of `obj`
+	// (located at `path`), given the RPATH chain of the object that loaded it.
+	// Mach-O LC_RPATH entries are inherited the same way as DT_RPATH.
+	fn new(
+		name: String,
+		obj: &Object,
+		path: &Path,
+		parent_rpath: &[PathBuf],
+		sysroot: Option<&Path>,
+	) -> Requester {
+		let loader_dir = path
+			.parent()
+			.unwrap_or_else(|| Path::new("/"))
+			.to_path_buf();
+		let mut rpath = Vec::new();
+		if obj.runpath.is_empty() {
+			match obj.type_.is_macho() {
+				true => rpath.extend(
+					obj.rpath
+						.iter()
+						.map(|dir| expand_lc_rpath(dir, &loader_dir, sysroot)),
+				),
+				false => rpath.extend_from_slice(&obj.rpath),
+			}
+		}
+		rpath.extend_from_slice(parent_rpath);
+		return Requester {
+			name,
+			rpath,
+			runpath: obj.runpath.clone(),
+			linker_script: false,
+			loader_dir,
+		};
+	}
+}
+
+// Target triplet used by MinGW toolchains for the given architecture.
+pub fn mingw_triplet(type_: &ObjectType, arch: &Arch) -> String {
+	let cpu = match arch.name(type_) {
+		"i386" => "i686",
+		"arm" => "armv7",
+		"unknown" if type_.is_64() => "x86_64",
+		"unknown" => "i686",
+		name => name,
 	};
+	return format!("{}-w64-mingw32", cpu);
+}
 
-	for dir in search_paths {
-		match find_in_directory(&name, &type_, &Path::new(dir)) {
-			Some(resolved) => {
-				let mut path = PathBuf::from(dir);
-				path.push(resolved);
-				return Status::Resolved(path);
+// Where MinGW toolchains install the DLLs of the target system.
+pub fn mingw_dirs(type_: &ObjectType, arch: &Arch) -> Vec<PathBuf> {
+	let triplet = mingw_triplet(type_, arch);
+	return [
+		format!("/usr/{}/sys-root/mingw/bin/", triplet), // Fedora
+		format!("/usr/{}/lib/", triplet),                // Debian
+	]
+	.iter()
+	.map(PathBuf::from)
+	.collect();
+}
+
+// Mach-O libraries are referred to by install names, which are paths,
+// possibly starting with one of the @-prefixed tokens understood by dyld.
+// Lookups in directories use just the file name.
+pub fn leaf_name(name: &str) -> String {
+	match name.rsplit('/').next() {
+		Some(leaf) => return String::from(leaf),
+		None => return String::from(name),
+	}
+}
+
+// LC_RPATH entries are expanded before being passed on to the objects loaded later,
+// as @loader_path refers to the object containing them. Absolute entries are taken
+// relative to the sysroot. @executable_path is left for macho_candidates().
+fn expand_lc_rpath(dir: &Path, loader_dir: &Path, sysroot: Option<&Path>) -> PathBuf {
+	match dir.is_absolute() {
+		true => return with_sysroot(sysroot, dir),
+		false => return expand_loader_path(&dir.to_string_lossy(), loader_dir),
+	}
+}
+
+fn expand_executable_path(dir: &Path, settings: &Settings) -> PathBuf {
+	let exe_dir = settings
+		.executable
+		.parent()
+		.unwrap_or_else(|| Path::new("/"));
+	match dir.strip_prefix("@executable_path") {
+		Ok(rest) => return exe_dir.join(rest),
+		Err(_) => return dir.to_path_buf(),
+	}
+}
+
+// The paths dyld would try for the given install name, in order.
+fn macho_candidates(
+	name: &str,
+	requester: &Requester,
+	settings: &Settings,
+) -> Vec<(PathBuf, SearchTier)> {
+	if let Some(rest) = name.strip_prefix("@rpath/") {
+		return requester
+			.rpath
+			.iter()
+			.map(|dir| {
+				(
+					expand_executable_path(dir, settings).join(rest),
+					SearchTier::Rpath,
+				)
+			})
+			.collect();
+	}
+	if let Some(rest) = name.strip_prefix("@loader_path/") {
+		return vec![(requester.loader_dir.join(rest), SearchTier::LoaderPath)];
+	}
+	if name.starts_with("@executable_path/") {
+		return vec![(
+			expand_executable_path(Path::new(name), settings),
+			SearchTier::ExecutablePath,
+		)];
+	}
+	if name.starts_with('/') {
+		return vec![(
+			with_sysroot(settings.sysroot.as_deref(), Path::new(name)),
+			SearchTier::InstallName,
+		)];
+	}
+	return vec![];
+}
+
+// The DLL as provided by the toolchain (or found in --search-dir),
+// regardless of what the application directory holds.
+pub fn find_in_toolchain(
+	name: &String,
+	type_: &ObjectType,
+	arch: &Arch,
+	settings: &Settings,
+) -> Option<PathBuf> {
+	let dirs = settings
+		.search_dirs
+		.iter()
+		.cloned()
+		.chain(mingw_dirs(type_, arch));
+	for dir in dirs {
+		if let Some(file_name) = find_in_directory(name, type_, &dir) {
+			let path = dir.join(file_name);
+			if check_compatibility(&path, type_, arch).is_ok() {
+				return Some(path);
+			}
+		}
+	}
+	return None;
+}
+
+// Mimic the DLL search order of Windows (with SafeDllSearchMode, the default):
+// KnownDLLs, DLL redirection, the application directory, System32 and finally PATH.
+// KnownDLLs and System32 are represented by the built-in lists, and since PATH
+// is not known in advance, the user-specified and MinGW directories take its place.
+fn resolve_pe(lookup: &Lookup, settings: &Settings) -> Status {
+	let name = lookup.name;
+	for (dir, tier) in settings.redirection.dirs_for(name) {
+		if let Some(path) = lookup.find_in_directories(std::iter::once(dir)) {
+			return Status::Resolved(path, tier);
+		}
+	}
+
+	let app_dir = settings
+		.executable
+		.parent()
+		.unwrap_or_else(|| Path::new("/"))
+		.to_path_buf();
+	if let Some(path) = lookup.find_in_directories(std::iter::once(&app_dir)) {
+		return Status::Resolved(path, SearchTier::AppDir);
+	}
+
+	if let (Some(reason), _) = check_lists(name, lookup.type_, lookup.arch, settings) {
+		return Status::Ignored(reason);
+	}
+
+	if let Some(path) = lookup.find_in_directories(&settings.search_dirs) {
+		return Status::Resolved(path, SearchTier::SearchDir);
+	}
+	if let Some(path) = lookup.find_in_directories(&mingw_dirs(lookup.type_, lookup.arch)) {
+		return Status::Resolved(path, SearchTier::System);
+	}
+	return Status::FailedToResolve;
+}
+
+pub fn resolve(
+	dep: &Dependency,
+	type_: &ObjectType,
+	arch: &Arch,
+	requester: &Requester,
+	settings: &Settings,
+	cache: &ObjectCache,
+) -> Status {
+	let name = &dep.name;
+	match check_lists(name, type_, arch, settings) {
+		// Apart from KnownDLLs, Windows prefers DLLs in the application directory over the system ones.
+		(Some(IgnoreReason::BuiltinList), _)
+			if type_.is_exe() && !is_known_dll(name, settings.windows_target) => {}
+		(Some(reason), _) => return Status::Ignored(reason),
+		(None, _) => {}
+	}
+
+	// API sets are resolved by the loader itself, as long as it knows of them.
+	// On older targets, they're looked up like regular DLLs (e.g. the UCRT forwarders),
+	// which can also be forced with --override.
+	let overridden = settings.override_list.is_match(name);
+	if let (true, false, Some(api_set)) = (type_.is_exe(), overridden, find_api_set(name)) {
+		match (
+			api_set.since <= settings.windows_target,
+			&settings.api_sets,
+			api_set.host,
+		) {
+			(true, ApiSetPolicy::Map, Some(host)) => return Status::ApiSet(String::from(host)),
+			(true, _, _) => return Status::Ignored(IgnoreReason::ApiSet),
+			(false, _, _) => {
+				if settings.verbose {
+					println!(
+						"\"{}\": API set not available before {}, looking for the DLL",
+						name,
+						api_set.since.name()
+					);
+				}
+			}
+		}
+	}
+
+	// Files listed by path in a linker script are not looked up. Their SONAME
+	// is not verified either, as the script may well refer to the full version.
+	if let Some(path) = &dep.path {
+		match check_compatibility(path, type_, arch) {
+			Ok(_) => return Status::Resolved(path.clone(), SearchTier::LinkerScript),
+			Err(reason) => {
+				if settings.verbose {
+					println!(
+						"\"{}\": skipping \"{}\": {}",
+						name,
+						path.to_string_lossy(),
+						reason
+					);
+				}
+				return Status::FailedToResolve;
+			}
+		}
+	}
+
+	let lookup_name = match type_.is_macho() {
+		true => leaf_name(name),
+		false => name.clone(),
+	};
+	let lookup = Lookup {
+		name: &lookup_name,
+		type_,
+		arch,
+		settings,
+		verify_soname: !requester.linker_script,
+		cache,
+	};
+
+	if type_.is_exe() {
+		return resolve_pe(&lookup, settings);
+	}
+
+	if let Some(dir) = &settings.exe_dir {
+		if let Some(path) = lookup.find_in_directories(std::iter::once(dir)) {
+			return Status::Resolved(path, SearchTier::ExeDir);
+		}
+	}
+	if let Some(path) = lookup.find_in_directories(&settings.search_dirs) {
+		return Status::Resolved(path, SearchTier::SearchDir);
+	}
+
+	// Mimic the order used by ld.so: DT_RPATH, LD_LIBRARY_PATH, DT_RUNPATH.
+	// The RPATH chain is ignored altogether if the requesting object has a RUNPATH.
+	if let ObjectType::Elf32 | ObjectType::Elf64 = type_ {
+		let rpath: &[PathBuf] = match requester.runpath.is_empty() {
+			true => &requester.rpath,
+			false => &[],
+		};
+		let tiers = [
+			(rpath, SearchTier::Rpath),
+			(&settings.ld_library_path, SearchTier::LdLibraryPath),
+			(&requester.runpath, SearchTier::Runpath),
+		];
+		for (dirs, tier) in tiers {
+			if let Some(path) = lookup.find_in_directories(dirs) {
+				return Status::Resolved(path, tier);
+			}
+		}
+	}
+
+	if type_.is_macho() {
+		for (path, tier) in macho_candidates(name, requester, settings) {
+			if path.is_file() && lookup.accept(&path) {
+				return Status::Resolved(path, tier);
+			}
+		}
+	}
+
+	// Like ld.so, consult the cache before falling back to walking the directories.
+	// Stale entries (pointing to files that no longer exist) are skipped.
+	if type_.is_elf() {
+		if let Some(path) = settings.ld_cache.get(name.as_str()) {
+			if path.exists() && lookup.accept(path) {
+				return Status::Resolved(path.clone(), SearchTier::LdCache);
 			}
-			None => { /* do nothing */ }
 		}
 	}
 
+	// Directories listed in ld.so.conf are searched before the built-in defaults.
+	if let Some(path) = lookup.find_in_directories(&settings.system_dirs) {
+		return Status::Resolved(path, SearchTier::System);
+	}
+
+	let search_paths: Vec<PathBuf> = match type_ {
+		ObjectType::Elf32 | ObjectType::Elf64 => {
+			let lib_dir = arch.lib_dir(type_);
+			[
+				format!("/{}/", lib_dir),
+				format!("/usr/{}/", lib_dir),
+				format!("/usr/local/{}/", lib_dir),
+			]
+			.iter()
+			.map(|dir| with_sysroot(settings.sysroot.as_deref(), Path::new(dir)))
+			.collect()
+		}
+		ObjectType::Exe32 | ObjectType::Exe64 => mingw_dirs(type_, arch),
+		// The default DYLD_FALLBACK_LIBRARY_PATH, minus $HOME/lib.
+		ObjectType::MachO32 | ObjectType::MachO64 => ["/usr/local/lib/", "/usr/lib/"]
+			.iter()
+			.map(|dir| with_sysroot(settings.sysroot.as_deref(), Path::new(dir)))
+			.collect(),
+	};
+
+	if let Some(path) = lookup.find_in_directories(&search_paths) {
+		return Status::Resolved(path, SearchTier::System);
+	}
+
 	return Status::FailedToResolve;
 }
 
+// Map the SONAMEs of all ELF libraries found in user-specified search directories
+// to their paths. Used as a last resort for libraries that could not be found by name,
+// e.g. when the directory only contains "libfoo.so.1.2.3" and not the "libfoo.so.1" symlink.
+fn build_soname_index(
+	type_: &ObjectType,
+	arch: &Arch,
+	settings: &Settings,
+) -> HashMap<String, PathBuf> {
+	let mut index = HashMap::new();
+	for dir in settings.exe_dir.iter().chain(settings.search_dirs.iter()) {
+		let entries = match fs::read_dir(dir) {
+			Ok(entries) => entries,
+			Err(_) => continue,
+		};
+		let mut paths: Vec<PathBuf> = entries
+			.flatten()
+			.map(|entry| entry.path())
+			.filter(|path| path.is_file())
+			.collect();
+		paths.sort();
+
+		for path in paths {
+			if check_compatibility(&path, type_, arch).is_err() {
+				continue;
+			}
+			if let Ok(Object {
+				soname: Some(soname),
+				..
+			}) = get_deps(&path)
+			{
+				index.entry(soname).or_insert(path);
+			}
+		}
+	}
+	return index;
+}
+
+pub struct Library {
+	pub status: Status,
+	// A library is only considered delay-loaded if every object that requests it does so lazily.
+	pub kind: DependencyKind,
+	// Names of the objects that requested this library, in the order they were encountered.
+	pub requested_by: Vec<String>,
+	// Libraries this one depends on, in the order listed in its headers.
+	// Always empty for libraries that were not resolved.
+	pub needs: Vec<Dependency>,
+	// Whether the library would have been ignored if not for --override.
+	pub overridden: bool,
+}
+
+fn linker_script_deps(
+	script: &Path,
+	inputs: Vec<ScriptInput>,
+	settings: &Settings,
+) -> Vec<Dependency> {
+	let script_dir = script.parent().unwrap_or_else(|| Path::new("/"));
+	return inputs
+		.into_iter()
+		.map(|input| match input {
+			ScriptInput::Library(name) => Dependency::normal(&name),
+			ScriptInput::File(path) => {
+				// Like ld, treat absolute paths as relative to the sysroot.
+				let path = match path.is_absolute() {
+					true => with_sysroot(settings.sysroot.as_deref(), &path),
+					false => script_dir.join(path),
+				};
+				let mut dep =
+					Dependency::normal(&path.file_name().unwrap_or_default().to_string_lossy());
+				dep.path = Some(path);
+				dep
+			}
+		})
+		.collect();
+}
+
 pub fn resolve_recursively(
 	obj: &Object,
 	settings: &Settings,
-) -> Result<HashMap<String, Status>, GetDepsError> {
-	let mut result: HashMap<String, Status> = HashMap::new();
+) -> Result<HashMap<String, Library>, GetDepsError> {
+	let mut result: HashMap<String, Library> = HashMap::new();
+	let mut soname_index: Option<HashMap<String, PathBuf>> = None;
+	let cache = ObjectCache::default();
 
-	let mut unresolved: Vec<String> = obj.deps.clone();
-	while !unresolved.is_empty() {
-		let entry = unresolved.pop().unwrap();
-		if result.contains_key(entry.as_str()) {
+	let exe_name = settings.executable_name();
+	let root = Rc::new(Requester::new(
+		exe_name,
+		obj,
+		&settings.executable,
+		&[],
+		settings.sysroot.as_deref(),
+	));
+	let mut unresolved: Vec<(Dependency, Rc<Requester>)> = obj
+		.deps
+		.iter()
+		.map(|dep| (dep.clone(), Rc::clone(&root)))
+		.collect();
+
+	while let Some((entry, requester)) = unresolved.pop() {
+		if let Some(library) = result.get_mut(entry.name.as_str()) {
+			if entry.kind == DependencyKind::Normal {
+				library.kind = DependencyKind::Normal;
+			}
+			if !library.requested_by.contains(&requester.name) {
+				library.requested_by.push(requester.name.clone());
+			}
 			continue;
 		}
 
-		let status = resolve(&entry, &obj.type_, settings);
-		if let Status::Resolved(path) = &status {
-			match get_deps(&path) {
-				Ok(mut sub_obj) => {
-					unresolved.append(&mut sub_obj.deps);
+		let mut status = resolve(&entry, &obj.type_, &obj.arch, &requester, settings, &cache);
+		if let (Status::FailedToResolve, true) = (&status, obj.type_.is_elf()) {
+			let index = soname_index
+				.get_or_insert_with(|| build_soname_index(&obj.type_, &obj.arch, settings));
+			if let Some(path) = index.get(entry.name.as_str()) {
+				status = Status::Resolved(path.clone(), SearchTier::SonameIndex);
+			}
+		}
+		let mut needs = Vec::new();
+		let script = match &status {
+			Status::Resolved(path, _) => read_linker_script(path),
+			_ => None,
+		};
+		if let (Some(inputs), Status::Resolved(path, tier)) = (script, &status) {
+			// The script takes the place of the library, so it uses the same search paths.
+			let script_requester = Rc::new(Requester {
+				name: entry.name.clone(),
+				rpath: requester.rpath.clone(),
+				runpath: requester.runpath.clone(),
+				linker_script: true,
+				loader_dir: requester.loader_dir.clone(),
+			});
+			needs = linker_script_deps(path, inputs, settings);
+			for dep in needs.iter() {
+				unresolved.push((dep.clone(), Rc::clone(&script_requester)));
+			}
+			status = Status::LinkerScript(path.clone(), *tier);
+		}
+		if let Status::ApiSet(host) = &status {
+			let host_dep = Dependency {
+				name: host.clone(),
+				kind: entry.kind,
+				path: None,
+			};
+			// The host DLL is loaded in place of the API set, with the same search paths.
+			let api_set_requester = Rc::new(Requester {
+				name: entry.name.clone(),
+				rpath: requester.rpath.clone(),
+				runpath: requester.runpath.clone(),
+				linker_script: false,
+				loader_dir: requester.loader_dir.clone(),
+			});
+			unresolved.push((host_dep.clone(), api_set_requester));
+			needs = vec![host_dep];
+		}
+
+		let parsed = match &status {
+			Status::Resolved(path, _) => Some((
+				path.clone(),
+				cache.get_deps_matching(path, &obj.type_, &obj.arch),
+			)),
+			_ => None,
+		};
+		match parsed {
+			Some((path, Ok(sub_obj))) => {
+				let sub_requester = Rc::new(Requester::new(
+					entry.name.clone(),
+					&sub_obj,
+					&path,
+					&requester.rpath,
+					settings.sysroot.as_deref(),
+				));
+				for dep in sub_obj.deps.iter() {
+					unresolved.push((dep.clone(), Rc::clone(&sub_requester)));
 				}
-				Err(e) => {
-					return Err(e);
+				needs = sub_obj.deps;
+			}
+			Some((_, Err(err))) => {
+				if settings.strict {
+					return Err(err);
+				}
+				if let Status::Resolved(path, tier) = status {
+					status = Status::Unreadable(path, tier, err);
 				}
 			}
+			None => { /* nothing to scan */ }
 		}
-		result.insert(entry, status);
+		let (_, overridden) = check_lists(&entry.name, &obj.type_, &obj.arch, settings);
+		result.insert(
+			entry.name,
+			Library {
+				status,
+				kind: entry.kind,
+				requested_by: vec![requester.name.clone()],
+				needs,
+				overridden,
+			},
+		);
 	}
 
 	return Ok(result);
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn settings(sysroot: Option<&str>) -> Settings {
+		let mut settings = Settings::new();
+		settings.executable = PathBuf::from("/opt/App/MacOS/prog");
+		settings.sysroot = sysroot.map(PathBuf::from);
+		return settings;
+	}
+
+	fn requester(rpath: &[&str]) -> Requester {
+		return Requester {
+			name: String::from("libthin.dylib"),
+			rpath: rpath.iter().map(PathBuf::from).collect(),
+			runpath: vec![],
+			linker_script: false,
+			loader_dir: PathBuf::from("/opt/App/lib"),
+		};
+	}
+
+	fn candidates(
+		name: &str,
+		requester: &Requester,
+		settings: &Settings,
+	) -> Vec<(String, &'static str)> {
+		return macho_candidates(name, requester, settings)
+			.iter()
+			.map(|(path, tier)| (path.to_string_lossy().into_owned(), tier.name()))
+			.collect();
+	}
+
+	#[test]
+	fn rpath_expansion() {
+		let settings = settings(None);
+		let with_rpath = requester(&["/opt/App/lib/sub", "@executable_path/../Frameworks"]);
+		assert_eq!(
+			candidates("@rpath/libfoo.dylib", &with_rpath, &settings),
+			[
+				(String::from("/opt/App/lib/sub/libfoo.dylib"), "rpath"),
+				(
+					String::from("/opt/App/MacOS/../Frameworks/libfoo.dylib"),
+					"rpath"
+				),
+			]
+		);
+		assert!(candidates("@rpath/libfoo.dylib", &requester(&[]), &settings).is_empty());
+	}
+
+	#[test]
+	fn loader_and_executable_path_expansion() {
+		let settings = settings(Some("/sysroot"));
+		let requester = requester(&[]);
+		assert_eq!(
+			candidates("@loader_path/libopt.dylib", &requester, &settings),
+			[(String::from("/opt/App/lib/libopt.dylib"), "loader-path")]
+		);
+		assert_eq!(
+			candidates(
+				"@executable_path/../Frameworks/liblazy.dylib",
+				&requester,
+				&settings
+			),
+			[(
+				String::from("/opt/App/MacOS/../Frameworks/liblazy.dylib"),
+				"executable-path"
+			)]
+		);
+		// Absolute install names are looked up in the sysroot, bare names aren't expanded at all.
+		assert_eq!(
+			candidates("/opt/local/lib/libz.1.dylib", &requester, &settings),
+			[(
+				String::from("/sysroot/opt/local/lib/libz.1.dylib"),
+				"install-name"
+			)]
+		);
+		assert!(candidates("libz.1.dylib", &requester, &settings).is_empty());
+	}
+
+	#[test]
+	fn rpath_from_object() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macho/libthin.dylib");
+		let obj = match get_deps(&path) {
+			Ok(obj) => obj,
+			Err(err) => panic!("{}", err),
+		};
+
+		// LC_RPATH entries of the object come before those inherited from its loaders.
+		let parent = [PathBuf::from("/opt/App/lib")];
+		let requester = Requester::new(String::from("libthin.dylib"), &obj, &path, &parent, None);
+		let dir = path.parent().unwrap().to_string_lossy().into_owned();
+		assert_eq!(
+			candidates("@rpath/libfoo.dylib", &requester, &settings(None)),
+			[
+				(format!("{}/../lib/libfoo.dylib", dir), "rpath"),
+				(
+					String::from("/opt/App/MacOS/../Frameworks/libfoo.dylib"),
+					"rpath"
+				),
+				(String::from("/opt/App/lib/libfoo.dylib"), "rpath"),
+			]
+		);
+		assert_eq!(
+			candidates("@loader_path/libopt.dylib", &requester, &settings(None)),
+			[(format!("{}/libopt.dylib", dir), "loader-path")]
+		);
+	}
+
+	#[test]
+	fn rpath_with_sysroot() {
+		let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/macho/libthin.dylib");
+		let obj = match get_deps(&path) {
+			Ok(obj) => obj,
+			Err(err) => panic!("{}", err),
+		};
+		let obj = Object {
+			rpath: vec![
+				PathBuf::from("@loader_path/../lib"),
+				PathBuf::from("/opt/local/lib"),
+			],
+			..obj
+		};
+
+		// Only entries that are absolute in the file are taken relative to the sysroot.
+		// @loader_path and the inherited entries already point into it.
+		let path = Path::new("/sysroot/opt/App/lib/libthin.dylib");
+		let parent = [PathBuf::from("/sysroot/usr/local/lib")];
+		let requester = Requester::new(
+			String::from("libthin.dylib"),
+			&obj,
+			path,
+			&parent,
+			Some(Path::new("/sysroot")),
+		);
+		assert_eq!(
+			candidates(
+				"@rpath/libfoo.dylib",
+				&requester,
+				&settings(Some("/sysroot"))
+			),
+			[
+				(
+					String::from("/sysroot/opt/App/lib/../lib/libfoo.dylib"),
+					"rpath"
+				),
+				(String::from("/sysroot/opt/local/lib/libfoo.dylib"), "rpath"),
+				(String::from("/sysroot/usr/local/lib/libfoo.dylib"), "rpath"),
+			]
+		);
+	}
+}
@@ -0,0 +1,580 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::{
+	convert::TryInto,
+	fmt::{Display, Formatter},
+	fs,
+	path::{Path, PathBuf},
+};
+
+extern crate goblin;
+use goblin::elf::dynamic::{
+	DT_AUDIT, DT_CONFIG, DT_DEPAUDIT, DT_NEEDED, DT_NULL, DT_RPATH, DT_RUNPATH, DT_SONAME,
+	DT_STRSZ, DT_STRTAB,
+};
+use goblin::elf::program_header::{PF_R, PF_W, PT_DYNAMIC, PT_LOAD, PT_NOTE};
+use goblin::elf::section_header::{SHT_DYNAMIC, SHT_STRTAB};
+use goblin::elf::Elf;
+
+use crate::permissions::write_in_place;
+
+const MIN_SEGMENT_ALIGN: u64 = 0x1000;
+
+// Not defined by goblin.
+const PT_GNU_PROPERTY: u32 = 0x6474e553;
+const DT_AUXILIARY: u64 = 0x7fff_fffd;
+const DT_FILTER: u64 = 0x7fff_ffff;
+
+// Dynamic entries (other than DT_RPATH and DT_RUNPATH) whose values point into the string table.
+const STRING_TAGS: [u64; 7] = [
+	DT_NEEDED,
+	DT_SONAME,
+	DT_AUXILIARY,
+	DT_FILTER,
+	DT_CONFIG,
+	DT_DEPAUDIT,
+	DT_AUDIT,
+];
+
+pub enum SetRpathError {
+	FailedToReadFile(PathBuf, std::io::Error),
+	FailedToParseFile(PathBuf, goblin::error::Error),
+	FailedToWriteFile(PathBuf, std::io::Error),
+	NotDynamic(PathBuf),
+	Malformed(PathBuf, &'static str),
+	NoRoom(PathBuf),
+}
+
+impl Display for SetRpathError {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		match self {
+			SetRpathError::FailedToReadFile(path, err) => write!(
+				f,
+				"Failed to read file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			SetRpathError::FailedToParseFile(path, err) => write!(
+				f,
+				"Failed to parse file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			SetRpathError::FailedToWriteFile(path, err) => write!(
+				f,
+				"Failed to write file \"{}\": {}",
+				path.to_string_lossy(),
+				err
+			),
+			SetRpathError::NotDynamic(path) => write!(
+				f,
+				"File \"{}\" has no dynamic section",
+				path.to_string_lossy()
+			),
+			SetRpathError::Malformed(path, what) => write!(
+				f,
+				"File \"{}\" is malformed: {}",
+				path.to_string_lossy(),
+				what
+			),
+			SetRpathError::NoRoom(path) => write!(
+				f,
+				"Cannot set RUNPATH of \"{}\": the new value does not fit in the string table \
+				and there is no spare program header to map an enlarged one",
+				path.to_string_lossy()
+			),
+		}
+	}
+}
+
+// Reads and writes ELF structures, honouring the class and byte order of the file.
+struct Editor {
+	bytes: Vec<u8>,
+	is_64: bool,
+	little_endian: bool,
+}
+
+impl Editor {
+	fn word_size(&self) -> usize {
+		match self.is_64 {
+			true => return 8,
+			false => return 4,
+		}
+	}
+
+	fn read(&self, offset: usize, size: usize) -> Option<u64> {
+		let slice = self.bytes.get(offset..offset + size)?;
+		return Some(match (size, self.little_endian) {
+			(4, true) => u32::from_le_bytes(slice.try_into().ok()?) as u64,
+			(4, false) => u32::from_be_bytes(slice.try_into().ok()?) as u64,
+			(_, true) => u64::from_le_bytes(slice.try_into().ok()?),
+			(_, false) => u64::from_be_bytes(slice.try_into().ok()?),
+		});
+	}
+
+	fn write(&mut self, offset: usize, size: usize, value: u64) {
+		let encoded = match (size, self.little_endian) {
+			(4, true) => (value as u32).to_le_bytes().to_vec(),
+			(4, false) => (value as u32).to_be_bytes().to_vec(),
+			(_, true) => value.to_le_bytes().to_vec(),
+			(_, false) => value.to_be_bytes().to_vec(),
+		};
+		self.bytes[offset..offset + size].copy_from_slice(&encoded);
+	}
+
+	fn read_word(&self, offset: usize) -> Option<u64> {
+		return self.read(offset, self.word_size());
+	}
+
+	fn write_word(&mut self, offset: usize, value: u64) {
+		self.write(offset, self.word_size(), value);
+	}
+
+	fn read_dynamic(&self, offset: usize, size: usize) -> Option<Vec<(u64, u64)>> {
+		let entry_size = 2 * self.word_size();
+		let mut entries = Vec::new();
+		for index in 0..(size / entry_size) {
+			let entry = offset + index * entry_size;
+			let tag = self.read_word(entry)?;
+			let value = self.read_word(entry + self.word_size())?;
+			entries.push((tag, value));
+		}
+		return Some(entries);
+	}
+
+	fn write_dynamic(&mut self, offset: usize, entries: &[(u64, u64)]) {
+		let entry_size = 2 * self.word_size();
+		for (index, (tag, value)) in entries.iter().enumerate() {
+			let entry = offset + index * entry_size;
+			self.write_word(entry, *tag);
+			self.write_word(entry + self.word_size(), *value);
+		}
+	}
+
+	// Update the location of a segment in its program header.
+	fn move_segment(&mut self, phdr: usize, p_type: u32, flags: u32, location: &Location) {
+		let w = self.word_size();
+		let (flags_at, offset_at) = match self.is_64 {
+			true => (phdr + 4, phdr + 8),
+			false => (phdr + 6 * 4, phdr + 4),
+		};
+		self.write(phdr, 4, p_type as u64);
+		self.write(flags_at, 4, flags as u64);
+		self.write_word(offset_at, location.offset);
+		self.write_word(offset_at + w, location.vaddr);
+		self.write_word(offset_at + 2 * w, location.vaddr);
+		self.write_word(offset_at + 3 * w, location.size);
+		self.write_word(offset_at + 4 * w, location.size);
+		if let Some(align) = location.align {
+			let align_at = match self.is_64 {
+				true => offset_at + 5 * w,
+				false => phdr + 7 * 4,
+			};
+			self.write_word(align_at, align);
+		}
+	}
+
+	// Update the location of a section in its section header.
+	fn move_section(&mut self, shdr: usize, location: &Location) {
+		let w = self.word_size();
+		let addr_at = match self.is_64 {
+			true => shdr + 16,
+			false => shdr + 12,
+		};
+		self.write_word(addr_at, location.vaddr);
+		self.write_word(addr_at + w, location.offset);
+		self.write_word(addr_at + 2 * w, location.size);
+	}
+}
+
+struct Location {
+	offset: u64,
+	vaddr: u64,
+	size: u64,
+	align: Option<u64>,
+}
+
+fn round_up(value: u64, align: u64) -> u64 {
+	return value.div_ceil(align) * align;
+}
+
+// Set the DT_RUNPATH of an ELF object, rewriting the file in place.
+// Any existing DT_RPATH is replaced as well, so that the two do not disagree.
+pub fn set_runpath(path: &Path, runpath: &str) -> Result<(), SetRpathError> {
+	let bytes = match fs::read(path) {
+		Ok(bytes) => bytes,
+		Err(e) => return Err(SetRpathError::FailedToReadFile(path.to_path_buf(), e)),
+	};
+	let elf = match Elf::parse(&bytes) {
+		Ok(elf) => elf,
+		Err(e) => return Err(SetRpathError::FailedToParseFile(path.to_path_buf(), e)),
+	};
+	let malformed = |what| SetRpathError::Malformed(path.to_path_buf(), what);
+
+	let phdrs = elf.program_headers.clone();
+	let shdrs = elf.section_headers.clone();
+	let (phoff, phentsize) = (elf.header.e_phoff, elf.header.e_phentsize as u64);
+	let (shoff, shentsize) = (elf.header.e_shoff, elf.header.e_shentsize as u64);
+	let mut editor = Editor {
+		is_64: elf.is_64,
+		little_endian: elf.little_endian,
+		bytes: Vec::new(),
+	};
+	// Offsets into the dynamic string table used by symbols and version information.
+	let mut string_refs: Vec<u64> = elf.dynsyms.iter().map(|sym| sym.st_name as u64).collect();
+	if let Some(verneed) = &elf.verneed {
+		for need in verneed.iter() {
+			string_refs.push(need.vn_file as u64);
+			string_refs.extend(need.iter().map(|aux| aux.vna_name as u64));
+		}
+	}
+	if let Some(verdef) = &elf.verdef {
+		for def in verdef.iter() {
+			string_refs.extend(def.iter().map(|aux| aux.vda_name as u64));
+		}
+	}
+	drop(elf);
+	editor.bytes = bytes;
+
+	let dyn_index = match phdrs.iter().position(|ph| ph.p_type == PT_DYNAMIC) {
+		Some(index) => index,
+		None => return Err(SetRpathError::NotDynamic(path.to_path_buf())),
+	};
+	let dyn_offset = phdrs[dyn_index].p_offset as usize;
+	let dyn_size = phdrs[dyn_index].p_filesz as usize;
+	let mut entries = match editor.read_dynamic(dyn_offset, dyn_size) {
+		Some(entries) => entries,
+		None => return Err(malformed("dynamic section out of bounds")),
+	};
+	// Ignore anything past the first DT_NULL, but remember how many slots are available.
+	let capacity = entries.len();
+	if let Some(end) = entries.iter().position(|(tag, _)| *tag == DT_NULL) {
+		entries.truncate(end);
+	}
+
+	string_refs.extend(
+		entries
+			.iter()
+			.filter(|(tag, _)| STRING_TAGS.contains(tag))
+			.map(|(_, value)| *value),
+	);
+
+	let find = |tag: u64| entries.iter().find(|e| e.0 == tag).map(|e| e.1);
+	let (strtab, strsz) = match (find(DT_STRTAB), find(DT_STRSZ)) {
+		(Some(strtab), Some(strsz)) => (strtab, strsz),
+		_ => return Err(malformed("no dynamic string table")),
+	};
+	let strtab_offset = match phdrs.iter().find(|ph| {
+		ph.p_type == PT_LOAD && strtab >= ph.p_vaddr && strtab < ph.p_vaddr + ph.p_filesz
+	}) {
+		Some(ph) => strtab - ph.p_vaddr + ph.p_offset,
+		None => return Err(malformed("dynamic string table is not mapped")),
+	};
+	if (strtab_offset + strsz) as usize > editor.bytes.len() {
+		return Err(malformed("dynamic string table out of bounds"));
+	}
+
+	// Offset of the NUL terminating the string that starts at the given offset.
+	let table = &editor.bytes[strtab_offset as usize..(strtab_offset + strsz) as usize];
+	let string_end = |offset: u64| match table.get(offset as usize..) {
+		Some(tail) => {
+			return offset + tail.iter().position(|b| *b == 0).unwrap_or(tail.len()) as u64
+		}
+		None => return offset,
+	};
+
+	// Only a single DT_RUNPATH is kept. Any other DT_RPATH or DT_RUNPATH entry is dropped,
+	// with the freed slot becoming padding at the end of the dynamic section.
+	let old_value = entries
+		.iter()
+		.find(|(tag, _)| *tag == DT_RUNPATH)
+		.or_else(|| entries.iter().find(|(tag, _)| *tag == DT_RPATH))
+		.map(|(_, value)| *value);
+	entries.retain(|(tag, _)| *tag != DT_RPATH && *tag != DT_RUNPATH);
+
+	// Try to reuse the space taken by the old value first. Linkers merge strings
+	// that are suffixes of other strings, so this is only safe if no other string overlaps it.
+	if let Some(old_value) = old_value {
+		if old_value >= strsz {
+			return Err(malformed("RPATH string out of bounds"));
+		}
+		let end = string_end(old_value);
+		let shared = string_refs
+			.iter()
+			.any(|offset| *offset <= end && string_end(*offset) >= old_value);
+		if runpath.len() as u64 <= end - old_value && !shared {
+			let start = (strtab_offset + old_value) as usize;
+			editor.bytes[start..start + (end - old_value) as usize].fill(0);
+			editor.bytes[start..start + runpath.len()].copy_from_slice(runpath.as_bytes());
+			// Make sure DT_RUNPATH takes effect even for objects that only had a DT_RPATH.
+			entries.push((DT_RUNPATH, old_value));
+			entries.resize(capacity, (DT_NULL, 0));
+			editor.write_dynamic(dyn_offset, &entries);
+			return write_file(path, editor.bytes);
+		}
+	}
+
+	// The new value does not fit. Append an enlarged copy of the string table
+	// to the end of the file and map it using a new PT_LOAD segment.
+	// PT_NOTE segments are not needed at run time, so one of them can be repurposed.
+	let last_load = match phdrs.iter().rposition(|ph| ph.p_type == PT_LOAD) {
+		Some(index) => index,
+		None => return Err(malformed("no loadable segments")),
+	};
+	let property = phdrs.iter().find(|ph| ph.p_type == PT_GNU_PROPERTY);
+	// PT_LOAD entries must be sorted by address, so only notes following the last one will do.
+	// Notes that double as PT_GNU_PROPERTY must be kept, as they are checked by the loader.
+	let spare = (last_load + 1..phdrs.len()).rev().find(|index| {
+		let ph = &phdrs[*index];
+		ph.p_type == PT_NOTE && property.map(|p| p.p_offset != ph.p_offset).unwrap_or(true)
+	});
+	let spare = match spare {
+		Some(index) => index,
+		None => return Err(SetRpathError::NoRoom(path.to_path_buf())),
+	};
+
+	let align = phdrs
+		.iter()
+		.filter(|ph| ph.p_type == PT_LOAD)
+		.map(|ph| ph.p_align)
+		.max()
+		.unwrap_or(MIN_SEGMENT_ALIGN)
+		.max(MIN_SEGMENT_ALIGN);
+	let mem_end = phdrs
+		.iter()
+		.filter(|ph| ph.p_type == PT_LOAD)
+		.map(|ph| ph.p_vaddr + ph.p_memsz)
+		.max()
+		.unwrap();
+
+	// The file offset and virtual address of a segment must be congruent modulo the alignment.
+	let seg_offset = round_up(editor.bytes.len() as u64, 16);
+	let seg_vaddr = round_up(mem_end, align) + seg_offset % align;
+
+	// If there is no spare slot in the dynamic section, it has to be moved as well.
+	let move_dynamic = entries.len() + 2 > capacity;
+	let mut segment: Vec<u8> = Vec::new();
+	let mut dynamic_location = None;
+	if move_dynamic {
+		let size = (entries.len() + 2) * 2 * editor.word_size();
+		dynamic_location = Some(Location {
+			offset: seg_offset,
+			vaddr: seg_vaddr,
+			size: size as u64,
+			align: None,
+		});
+		segment.resize(size, 0);
+	}
+
+	let strtab_location = Location {
+		offset: seg_offset + segment.len() as u64,
+		vaddr: seg_vaddr + segment.len() as u64,
+		size: strsz + runpath.len() as u64 + 1,
+		align: None,
+	};
+	segment
+		.extend_from_slice(&editor.bytes[strtab_offset as usize..(strtab_offset + strsz) as usize]);
+	segment.extend_from_slice(runpath.as_bytes());
+	segment.push(0);
+
+	for entry in entries.iter_mut() {
+		match entry.0 {
+			DT_STRTAB => entry.1 = strtab_location.vaddr,
+			DT_STRSZ => entry.1 = strtab_location.size,
+			_ => {}
+		}
+	}
+	entries.push((DT_RUNPATH, strsz));
+	let slots = match move_dynamic {
+		true => entries.len() + 1,
+		false => capacity,
+	};
+	entries.resize(slots, (DT_NULL, 0));
+
+	editor.bytes.resize(seg_offset as usize, 0);
+	editor.bytes.extend_from_slice(&segment);
+	let segment_location = Location {
+		offset: seg_offset,
+		vaddr: seg_vaddr,
+		size: segment.len() as u64,
+		align: Some(align),
+	};
+	// ld.so may write to the dynamic section, so make the segment writable if it's there.
+	let flags = match move_dynamic {
+		true => PF_R | PF_W,
+		false => PF_R,
+	};
+	editor.move_segment(
+		(phoff + spare as u64 * phentsize) as usize,
+		PT_LOAD,
+		flags,
+		&segment_location,
+	);
+
+	match &dynamic_location {
+		Some(location) => {
+			editor.write_dynamic(location.offset as usize, &entries);
+			let dyn_phdr = (phoff + dyn_index as u64 * phentsize) as usize;
+			editor.move_segment(dyn_phdr, PT_DYNAMIC, PF_R | PF_W, location);
+		}
+		None => editor.write_dynamic(dyn_offset, &entries),
+	}
+
+	// Keep the section headers in sync, so tools like readelf show the new value.
+	for (index, sh) in shdrs.iter().enumerate() {
+		let shdr = (shoff + index as u64 * shentsize) as usize;
+		if sh.sh_type == SHT_STRTAB && sh.sh_addr == strtab && sh.sh_addr != 0 {
+			editor.move_section(shdr, &strtab_location);
+		}
+		if let (SHT_DYNAMIC, Some(location)) = (sh.sh_type, &dynamic_location) {
+			editor.move_section(shdr, location);
+		}
+	}
+
+	return write_file(path, editor.bytes);
+}
+
+// Check if the object's search path is exactly what set_runpath() would make it.
+pub fn has_runpath(path: &Path, runpath: &str) -> bool {
+	let bytes = match fs::read(path) {
+		Ok(bytes) => bytes,
+		Err(_) => return false,
+	};
+	match Elf::parse(&bytes) {
+		Ok(elf) => return elf.rpaths.is_empty() && elf.runpaths == [runpath],
+		Err(_) => return false,
+	}
+}
+
+fn write_file(path: &Path, bytes: Vec<u8>) -> Result<(), SetRpathError> {
+	match write_in_place(path, bytes) {
+		Ok(_) => return Ok(()),
+		Err(e) => return Err(SetRpathError::FailedToWriteFile(path.to_path_buf(), e)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Generated by tests/fixtures/elf/gen.sh.
+	fn patched(fixture: &str, runpath: &str) -> Vec<u8> {
+		let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+			.join("tests/fixtures/elf")
+			.join(fixture);
+		let path = std::env::temp_dir().join(format!(
+			"copydeps-test-{}-{}-{}",
+			std::process::id(),
+			runpath.len(),
+			fixture
+		));
+		fs::copy(&source, &path).unwrap();
+		let result = set_runpath(&path, runpath);
+		let bytes = fs::read(&path).unwrap();
+		let _ = fs::remove_file(&path);
+		if let Err(err) = result {
+			panic!("{}", err);
+		}
+		return bytes;
+	}
+
+	fn parse(bytes: &[u8]) -> Elf<'_> {
+		match Elf::parse(bytes) {
+			Ok(elf) => return elf,
+			Err(err) => panic!("{}", err),
+		}
+	}
+
+	fn symbol_names<'a>(elf: &'a Elf) -> Vec<&'a str> {
+		return elf
+			.dynsyms
+			.iter()
+			.map(|sym| elf.dynstrtab.get_at(sym.st_name).unwrap())
+			.collect();
+	}
+
+	fn count_tag(elf: &Elf, tag: u64) -> usize {
+		let dynamic = elf.dynamic.as_ref().unwrap();
+		return dynamic.dyns.iter().filter(|d| d.d_tag == tag).count();
+	}
+
+	// Check that the dynamic entries were updated, and nothing else was damaged along the way.
+	fn check_patched(bytes: &[u8], runpath: &str, soname: &str) {
+		let elf = parse(bytes);
+		assert_eq!(elf.runpaths, [runpath]);
+		assert!(elf.rpaths.is_empty());
+		assert_eq!(count_tag(&elf, DT_RUNPATH), 1);
+		assert_eq!(count_tag(&elf, DT_RPATH), 0);
+		assert_eq!(elf.soname, Some(soname));
+		assert_eq!(elf.libraries, ["x.so"]);
+		assert_eq!(symbol_names(&elf), ["", "x_value", "fixture_value"]);
+
+		// PT_LOAD segments must be sorted, must not overlap, and must be backed by the file.
+		let loads: Vec<_> = elf
+			.program_headers
+			.iter()
+			.filter(|ph| ph.p_type == PT_LOAD)
+			.collect();
+		for pair in loads.windows(2) {
+			assert!(pair[0].p_vaddr + pair[0].p_memsz <= pair[1].p_vaddr);
+		}
+		for ph in loads.iter() {
+			assert_eq!(ph.p_offset % ph.p_align, ph.p_vaddr % ph.p_align);
+			assert!(ph.p_offset + ph.p_filesz <= bytes.len() as u64);
+		}
+	}
+
+	#[test]
+	fn runpath_in_place() {
+		let original = fs::read(
+			Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/elf/libfixture.so"),
+		)
+		.unwrap();
+		let bytes = patched("libfixture.so", "$ORIGIN");
+		check_patched(&bytes, "$ORIGIN", "libfixture.so");
+
+		// The new value fits in place of the old one, so the layout does not change.
+		assert_eq!(bytes.len(), original.len());
+		let before = parse(&original);
+		let after = parse(&bytes);
+		assert_eq!(before.program_headers, after.program_headers);
+	}
+
+	#[test]
+	fn runpath_appended() {
+		let runpath = "$ORIGIN/../lib/a/path/that/is/too/long/to/fit";
+		let bytes = patched("libfixture.so", runpath);
+		check_patched(&bytes, runpath, "libfixture.so");
+
+		// The PT_NOTE segment was turned into a PT_LOAD mapping the new string table.
+		let elf = parse(&bytes);
+		assert!(elf.program_headers.iter().all(|ph| ph.p_type != PT_NOTE));
+		let last = elf
+			.program_headers
+			.iter()
+			.rfind(|ph| ph.p_type == PT_LOAD)
+			.unwrap();
+		assert_eq!(last.p_offset + last.p_filesz, bytes.len() as u64);
+	}
+
+	#[test]
+	fn runpath_sharing_string() {
+		// "merged.so" is stored as the tail of "libmerged.so", so it must not be overwritten.
+		let bytes = patched("libmerged.so", "lib");
+		check_patched(&bytes, "lib", "libmerged.so");
+	}
+}
@@ -15,6 +15,7 @@
  * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
  */
 use std::{
+	collections::HashMap,
 	env,
 	fmt::{Display, Formatter},
 	fs,
@@ -31,13 +32,21 @@ extern crate regex;
 use regex::RegexSet;
 use regex::RegexSetBuilder;
 
+use crate::api_set::ApiSetPolicy;
 use crate::exit_status::*;
+use crate::layout::{default_lib_dir, expand_template, Layout, LayoutError, DEFAULT_BIN_DIR};
+use crate::ld_so_conf::with_sysroot;
+use crate::manifest::ManifestEntry;
+use crate::parser::Object;
+use crate::preserve::Preserve;
 use crate::version::*;
+use crate::windows_search::Redirection;
+use crate::windows_version::WindowsVersion;
 
 fn print_help() {
 	print!(
 		concat!(
-			"{NAME} finds and copies all .so / .dll files needed by a program to run.\n",
+			"{NAME} finds and copies all .so / .dll / .dylib files needed by a program to run.\n",
 			"This can be useful when you want to bundle an application\n",
 			"together will all its dependencies.\n",
 			"\n",
@@ -48,30 +57,99 @@ fn print_help() {
 			"- 64-bit ELF\n",
 			"- i386 Microsoft Windows executable\n",
 			"- x86_64 Microsoft Windows executable\n",
+			"- 32-bit and 64-bit Mach-O, including universal (fat) binaries\n",
 			"\n",
-			"TARGET-DIR specifies the directory to copy the .so / .dll files to.\n",
+			"TARGET-DIR specifies the directory to copy the .so / .dll / .dylib files to.\n",
 			"When omitted, defaults to the directory of the target executable.\n",
 			"\n",
 			"Program options:\n",
+			"--api-sets MODE\n",
+			"  Select how Windows API set DLLs (api-ms-win-*, ext-ms-*) are handled.\n",
+			"  MODE can be \"ignore\" (the default), which treats them as part of the OS,\n",
+			"  or \"map\", which replaces them with the DLL implementing them (e.g. ucrtbase.dll).\n",
+			"--bin-dir TEMPLATE\n",
+			"  Copy the executable to TARGET-DIR/TEMPLATE. Implies --layout.\n",
+			"--clean\n",
+			"  Remove files copied by an earlier run that are no longer needed.\n",
+			"  Only files listed in the manifest, and unchanged since, are removed.\n",
 			"--dry-run\n",
 			"  Print the list of dependencies without actually copying the .so / .dll files.\n",
+			"  DLLs only ever imported via the delay-load mechanism are marked as such.\n",
 			"--exedir\n",
 			"  Include the directory of the executable in the .so / .dll resolve paths.\n",
 			"  Files found in the exedir are preferred over those found anywhere else.\n",
+			"  Windows executables always have their directory searched first.\n",
+			"--format FORMAT\n",
+			"  Select the output format. FORMAT can be \"text\" (the default), \"json\" or \"dot\".\n",
+			"  In JSON mode, a report listing all the dependencies, their status\n",
+			"  and (unless --dry-run is used) what was done with them is printed at the end.\n",
+			"  In DOT mode, the dependency graph is printed in Graphviz format at the end.\n",
 			"--ignore PATTERN\n",
 			"  Add the regular expression PATTERN to the ignore-list\n",
 			"  (.so / .dll names that should not be resolved nor copied over).\n",
+			"--install-name-prefix PREFIX\n",
+			"  Change the install names of copied Mach-O libraries, and the references to them,\n",
+			"  to PREFIX/NAME (e.g. '@rpath'). Only the copies in the target directory are modified.\n",
+			"  With \"auto\", PREFIX points at the library directory using @executable_path.\n",
+			"  With @rpath, an LC_RPATH pointing at the library directory is added as well.\n",
+			"--layout\n",
+			"  Treat TARGET-DIR as the root of a bundle: copy the executable to its bin/\n",
+			"  subdirectory and libraries to lib/ (ELF) or bin/ (Windows).\n",
+			"--lib-dir TEMPLATE\n",
+			"  Copy libraries to TARGET-DIR/TEMPLATE. Implies --layout.\n",
+			"  Templates can use the {{arch}}, {{lib}} (e.g. lib64) and {{triplet}} variables.\n",
 			"--no-clobber\n",
 			"  Do not overwrite .so / .dll files already existing in the target directory.\n",
+			"--no-ld-cache\n",
+			"  Do not use /etc/ld.so.cache when resolving ELF dependencies.\n",
 			"--override PATTERN\n",
 			"  Add the regular expression PATTERN to the override-list\n",
 			"  (.so / .dll names that should always be resolved and copied over).\n",
 			"  Overrides have precedence over ignores.\n",
+			"--preserve[=ATTRIBUTES]\n",
+			"  Preserve the given attributes of the copied files. ATTRIBUTES is a comma-separated\n",
+			"  list of \"mode\", \"ownership\", \"timestamps\", \"xattr\" and \"all\".\n",
+			"  When omitted, defaults to \"mode,ownership,timestamps\".\n",
+			"  Ownership failures are only reported if \"ownership\" is listed explicitly.\n",
 			"--search-dir DIRECTORY\n",
 			"  Add DIRECTORY to the list of paths to search when resolving .so / .dll names.\n",
 			"  User-specified directories take precedence over system paths.\n",
+			"  ELF libraries that cannot be found by name are also looked up by SONAME.\n",
+			"--set-rpath RUNPATH\n",
+			"  Set the RUNPATH of each copied ELF library to RUNPATH (e.g. '$ORIGIN').\n",
+			"  Only the copies in the target directory are modified.\n",
+			"  The executable is only patched if copied as well (see --layout).\n",
+			"  With \"auto\", the RUNPATH is computed to point at the library directory.\n",
+			"--strict\n",
+			"  Stop immediately if one of the libraries cannot be parsed.\n",
+			"  By default, such libraries are reported and the rest are processed as usual.\n",
+			"--symlinks\n",
+			"  Reproduce symlink chains (e.g. libfoo.so.1 -> libfoo.so.1.2.3)\n",
+			"  in the target directory, instead of copying each library as a regular file.\n",
+			"--sysroot DIRECTORY\n",
+			"  Treat DIRECTORY as the root of the target system when looking for\n",
+			"  ELF system libraries, reading /etc/ld.so.conf and resolving absolute\n",
+			"  Mach-O install names.\n",
+			"--tree\n",
+			"  Print the dependencies as a tree, showing which object requested which library.\n",
+			"  Libraries that appear more than once are only expanded on first occurrence.\n",
+			"  Implies --dry-run.\n",
+			"--update\n",
+			"  Do not copy files whose contents in the target directory are already up to date.\n",
 			"--verbose\n",
 			"  Print the names of the dependencies as they're being copied over.\n",
+			"  Also report any candidate files skipped due to an architecture mismatch.\n",
+			"--why LIBRARY\n",
+			"  Print every chain of dependencies leading from the executable to LIBRARY,\n",
+			"  along with where each library in the chain was found (or why it was ignored).\n",
+			"  Implies --dry-run.\n",
+			"--windows-target VERSION\n",
+			"  Select the oldest version of Windows the program is meant to run on.\n",
+			"  VERSION can be \"xp\", \"vista\", \"7\", \"8\", \"8.1\", \"10\" (the default) or \"11\".\n",
+			"  DLLs shipped with that version are not copied; those added later are.\n",
+			"  API sets not available on that version are looked up like regular DLLs.\n",
+			"  Bundled DLLs that are KnownDLLs on that version are reported,\n",
+			"  as Windows always loads those from System32.\n",
 			""
 		),
 		NAME = PROGRAM_NAME,
@@ -105,35 +183,92 @@ fn canonicalize_path(path: &Path) -> Result<PathBuf, SettingsError> {
 	}
 }
 
+pub enum OutputFormat {
+	Text,
+	Json,
+	Dot,
+}
+
 pub struct Settings {
+	pub api_sets: ApiSetPolicy,
+	pub clean: bool,
 	pub dry_run: bool,
+	pub exe_dir: Option<PathBuf>,
 	pub executable: PathBuf,
+	pub format: OutputFormat,
 	pub ignore_list: RegexSet,
+	pub install_name_prefix: Option<String>,
+	pub install_names: HashMap<String, String>,
+	pub layout: Layout,
+	pub ld_cache: HashMap<String, PathBuf>,
+	pub ld_library_path: Vec<PathBuf>,
+	pub manifest: HashMap<PathBuf, ManifestEntry>,
 	pub no_clobber: bool,
+	pub no_ld_cache: bool,
 	pub override_list: RegexSet,
+	pub preserve: Preserve,
+	pub redirection: Redirection,
 	pub search_dirs: Vec<PathBuf>,
+	pub set_rpath: Option<String>,
+	pub strict: bool,
+	pub symlinks: bool,
+	pub sysroot: Option<PathBuf>,
+	pub system_dirs: Vec<PathBuf>,
 	pub target_dir: PathBuf,
+	pub tree: bool,
+	pub update: bool,
 	pub verbose: bool,
+	pub why: Option<String>,
+	// The oldest version of Windows the program is meant to run on.
+	pub windows_target: WindowsVersion,
 
+	bin_dir_str: Option<String>,
 	ignore_list_str: Vec<String>,
+	layout_requested: bool,
+	lib_dir_str: Option<String>,
 	override_list_str: Vec<String>,
 }
 
 impl Settings {
 	// Unfortunately for us, RegexSet does not implement Default
-	fn new() -> Settings {
+	pub fn new() -> Settings {
 		let empty_vector: Vec<&str> = vec![];
 		Settings {
+			api_sets: ApiSetPolicy::Ignore,
+			clean: false,
 			dry_run: false,
+			exe_dir: None,
 			executable: PathBuf::new(),
+			format: OutputFormat::Text,
 			ignore_list: RegexSet::new(&empty_vector).unwrap(),
+			install_name_prefix: None,
+			install_names: HashMap::new(),
+			layout: Layout::flat(),
+			ld_cache: HashMap::new(),
+			ld_library_path: vec![],
+			manifest: HashMap::new(),
 			no_clobber: false,
+			no_ld_cache: false,
 			override_list: RegexSet::new(&empty_vector).unwrap(),
+			preserve: Preserve::default(),
+			redirection: Redirection::default(),
 			search_dirs: vec![],
+			set_rpath: None,
+			strict: false,
+			symlinks: false,
+			sysroot: None,
+			system_dirs: vec![],
 			target_dir: PathBuf::new(),
+			tree: false,
+			update: false,
 			verbose: false,
+			why: None,
+			windows_target: WindowsVersion::Win10,
 
+			bin_dir_str: None,
 			ignore_list_str: vec![],
+			layout_requested: false,
+			lib_dir_str: None,
 			override_list_str: vec![],
 		}
 	}
@@ -156,12 +291,29 @@ impl Settings {
 		opts.optmulti("", "blacklist", "", "");
 		opts.optmulti("", "whitelist", "", "");
 
+		opts.optopt("", "install-name-prefix", "", "");
 		opts.optmulti("", "search-dir", "", "");
+		opts.optopt("", "set-rpath", "", "");
+		opts.optflag("", "symlinks", "");
+		opts.optopt("", "sysroot", "", "");
 
+		opts.optopt("", "api-sets", "", "");
+		opts.optflag("", "clean", "");
 		opts.optflag("", "dry-run", "");
+		opts.optflag("", "layout", "");
+		opts.optopt("", "bin-dir", "", "");
+		opts.optopt("", "lib-dir", "", "");
 		opts.optflag("", "exedir", "");
+		opts.optopt("", "format", "", "");
+		opts.optflag("", "tree", "");
+		opts.optopt("", "why", "", "");
+		opts.optopt("", "windows-target", "", "");
 
 		opts.optflag("", "no-clobber", "");
+		opts.optflagopt("", "preserve", "", "");
+		opts.optflag("", "no-ld-cache", "");
+		opts.optflag("", "strict", "");
+		opts.optflag("", "update", "");
 		opts.optflag("", "verbose", "");
 
 		let matches = opts.parse(args)?;
@@ -218,22 +370,126 @@ impl Settings {
 			settings.search_dirs.push(entry_pb);
 		}
 
+		if let Some(sysroot) = matches.opt_str("sysroot") {
+			let sysroot = PathBuf::from(sysroot);
+			verify_dir(&sysroot)?;
+			settings.sysroot = Some(canonicalize_path(&sysroot)?);
+		}
+
+		if let Some(value) = env::var_os("LD_LIBRARY_PATH") {
+			// The entries stand for directories on the target system, so they're taken
+			// relative to the sysroot. Like ld.so, resolve relative entries against
+			// the working directory first.
+			let cwd = env::current_dir().unwrap_or_default();
+			settings.ld_library_path = env::split_paths(&value)
+				.filter(|path| !path.as_os_str().is_empty())
+				.map(|path| with_sysroot(settings.sysroot.as_deref(), &cwd.join(path)))
+				.collect();
+		}
+
 		if matches.opt_present("dry-run") {
 			settings.dry_run = true;
 		}
 		if matches.opt_present("exedir") {
-			settings.search_dirs.insert(0, executable_dir.clone());
+			settings.exe_dir = Some(executable_dir.clone());
+		}
+		if matches.opt_present("clean") {
+			settings.clean = true;
 		}
 		if matches.opt_present("no-clobber") {
 			settings.no_clobber = true;
 		}
+		if matches.opt_present("no-ld-cache") {
+			settings.no_ld_cache = true;
+		}
+		if matches.opt_present("preserve") {
+			let list = matches.opt_str("preserve");
+			settings.preserve = match Preserve::parse(list.as_deref()) {
+				Ok(preserve) => preserve,
+				Err(attribute) => return Err(SettingsError::UnknownAttribute(attribute)),
+			};
+		}
+		settings.set_rpath = matches.opt_str("set-rpath");
+		settings.install_name_prefix = matches.opt_str("install-name-prefix");
+		settings.bin_dir_str = matches.opt_str("bin-dir");
+		settings.lib_dir_str = matches.opt_str("lib-dir");
+		settings.layout_requested = matches.opt_present("layout")
+			|| settings.bin_dir_str.is_some()
+			|| settings.lib_dir_str.is_some();
+		if matches.opt_present("strict") {
+			settings.strict = true;
+		}
+		if matches.opt_present("symlinks") {
+			settings.symlinks = true;
+		}
+		if matches.opt_present("tree") {
+			settings.tree = true;
+			settings.dry_run = true;
+		}
+		if matches.opt_present("update") {
+			settings.update = true;
+		}
 		if matches.opt_present("verbose") {
 			settings.verbose = true;
 		}
+		if let Some(library) = matches.opt_str("why") {
+			if settings.tree {
+				return Err(SettingsError::IncompatibleOptions("--tree", "--why"));
+			}
+			settings.why = Some(library);
+			settings.dry_run = true;
+		}
+
+		if let Some(mode) = matches.opt_str("api-sets") {
+			settings.api_sets = match mode.as_str() {
+				"ignore" => ApiSetPolicy::Ignore,
+				"map" => ApiSetPolicy::Map,
+				_ => return Err(SettingsError::UnknownApiSetPolicy(mode)),
+			};
+		}
+		if let Some(version) = matches.opt_str("windows-target") {
+			settings.windows_target = match WindowsVersion::parse(&version) {
+				Some(version) => version,
+				None => return Err(SettingsError::UnknownWindowsVersion(version)),
+			};
+		}
+
+		if let Some(format) = matches.opt_str("format") {
+			settings.format = match format.as_str() {
+				"text" => OutputFormat::Text,
+				"json" => OutputFormat::Json,
+				"dot" => OutputFormat::Dot,
+				_ => return Err(SettingsError::UnknownFormat(format)),
+			};
+		}
+		// Messages printed by --verbose would end up interleaved with the machine-readable output.
+		let format_name = match settings.format {
+			OutputFormat::Text => None,
+			OutputFormat::Json => Some("--format json"),
+			OutputFormat::Dot => Some("--format dot"),
+		};
+		if let Some(format_name) = format_name {
+			if settings.verbose {
+				return Err(SettingsError::IncompatibleOptions(format_name, "--verbose"));
+			}
+			if settings.tree {
+				return Err(SettingsError::IncompatibleOptions(format_name, "--tree"));
+			}
+			if settings.why.is_some() {
+				return Err(SettingsError::IncompatibleOptions(format_name, "--why"));
+			}
+		}
 
 		return Ok(settings);
 	}
 
+	pub fn executable_name(&self) -> String {
+		return match self.executable.file_name() {
+			Some(name) => name.to_string_lossy().into_owned(),
+			None => self.executable.to_string_lossy().into_owned(),
+		};
+	}
+
 	pub fn compile_lists(&mut self, case_insensitive: bool) -> Result<(), ListCompilationError> {
 		self.ignore_list = match RegexSetBuilder::new(&self.ignore_list_str)
 			.case_insensitive(case_insensitive)
@@ -252,6 +508,29 @@ impl Settings {
 
 		return Ok(());
 	}
+
+	// Directory templates can only be expanded once the executable's type is known.
+	pub fn compile_layout(&mut self, executable: &Object) -> Result<(), LayoutError> {
+		if !self.layout_requested {
+			return Ok(());
+		}
+
+		let bin_dir = self.bin_dir_str.as_deref().unwrap_or(DEFAULT_BIN_DIR);
+		let lib_dir = match &self.lib_dir_str {
+			Some(template) => template.as_str(),
+			None => default_lib_dir(executable),
+		};
+		self.layout = Layout {
+			bin_dir: Some(expand_template(bin_dir, executable)?),
+			lib_dir: expand_template(lib_dir, executable)?,
+		};
+		return Ok(());
+	}
+
+	// Where libraries should be copied to.
+	pub fn lib_target_dir(&self) -> PathBuf {
+		return self.target_dir.join(&self.layout.lib_dir);
+	}
 }
 
 pub enum SettingsError {
@@ -263,6 +542,11 @@ pub enum SettingsError {
 	DirectoryNotFound(PathBuf, std::io::Error),
 	DirectoryNotADirectory(PathBuf),
 	FailedToCanonicalizePath(PathBuf, std::io::Error),
+	UnknownFormat(String),
+	UnknownAttribute(String),
+	UnknownApiSetPolicy(String),
+	UnknownWindowsVersion(String),
+	IncompatibleOptions(&'static str, &'static str),
 }
 
 impl From<getopts::Fail> for SettingsError {
@@ -309,6 +593,33 @@ impl Display for SettingsError {
 				path.to_string_lossy(),
 				err
 			),
+			SettingsError::UnknownFormat(format) => write!(
+				f,
+				"Unknown output format \"{}\" (expected \"text\", \"json\" or \"dot\")",
+				format
+			),
+			SettingsError::UnknownAttribute(attribute) => write!(
+				f,
+				"Unknown attribute \"{}\" for --preserve (expected \"mode\", \"ownership\", \"timestamps\", \"xattr\" or \"all\")",
+				attribute
+			),
+			SettingsError::UnknownApiSetPolicy(mode) => write!(
+				f,
+				"Unknown mode \"{}\" for --api-sets (expected \"ignore\" or \"map\")",
+				mode
+			),
+			SettingsError::UnknownWindowsVersion(version) => write!(
+				f,
+				"Unknown version \"{}\" for --windows-target (expected \"xp\", \"vista\", \"7\", \"8\", \"8.1\", \"10\" or \"11\")",
+				version
+			),
+			SettingsError::IncompatibleOptions(first, second) => {
+				write!(
+					f,
+					"Options {} and {} cannot be used together",
+					first, second
+				)
+			}
 		}
 	}
 }
@@ -0,0 +1,61 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt::Write;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+extern crate sha2;
+use sha2::{Digest, Sha256};
+
+fn to_hex(digest: &[u8]) -> String {
+	let mut result = String::with_capacity(2 * digest.len());
+	for byte in digest {
+		let _ = write!(result, "{:02x}", byte);
+	}
+	return result;
+}
+
+// Hex-encoded SHA-256 digest of the file's contents.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+	let mut file = File::open(path)?;
+	let mut hasher = Sha256::new();
+	io::copy(&mut file, &mut hasher)?;
+	return Ok(to_hex(&hasher.finalize()));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn hex_encoding() {
+		let vectors: [(&[u8], &str); 2] = [
+			(
+				b"",
+				"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+			),
+			(
+				b"abc",
+				"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+			),
+		];
+		for (data, expected) in vectors {
+			assert_eq!(to_hex(&Sha256::digest(data)), expected);
+		}
+	}
+}
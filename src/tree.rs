@@ -0,0 +1,104 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{Dependency, DependencyKind, Object};
+use crate::resolver::{Library, Status};
+use crate::settings::Settings;
+
+const INDENT: &str = "    ";
+
+struct TreePrinter<'a> {
+	deps: &'a HashMap<String, Library>,
+	// Libraries whose subtree has already been printed.
+	expanded: HashSet<&'a str>,
+}
+
+impl<'a> TreePrinter<'a> {
+	fn print_node(&mut self, dep: &'a Dependency, depth: usize) {
+		let indent = INDENT.repeat(depth);
+		let kind = match dep.kind {
+			DependencyKind::Normal => "",
+			DependencyKind::DelayLoad => " (delay-load)",
+			DependencyKind::Weak => " (weak)",
+		};
+
+		let library = match self.deps.get(dep.name.as_str()) {
+			Some(library) => library,
+			None => return,
+		};
+		let (path, note) = match &library.status {
+			Status::Ignored(_) => {
+				println!("{}{} (ignored){}", indent, dep.name, kind);
+				return;
+			}
+			Status::FailedToResolve => {
+				println!("{}{} => not found{}", indent, dep.name, kind);
+				return;
+			}
+			Status::Resolved(path, _) => (path.to_string_lossy(), ""),
+			Status::LinkerScript(path, _) => (path.to_string_lossy(), " (linker script)"),
+			Status::ApiSet(host) => (Cow::from(host.as_str()), " (API set)"),
+			Status::Unreadable(path, _, _) => {
+				println!(
+					"{}{} => {} (unreadable){}",
+					indent,
+					dep.name,
+					path.to_string_lossy(),
+					kind
+				);
+				return;
+			}
+		};
+
+		// Print each subtree only once. This keeps the output readable
+		// and also protects against dependency cycles.
+		if !self.expanded.insert(dep.name.as_str()) {
+			let marker = match library.needs.is_empty() {
+				true => "",
+				false => " [see above]",
+			};
+			println!(
+				"{}{} => {}{}{}{}",
+				indent, dep.name, path, note, kind, marker
+			);
+			return;
+		}
+
+		println!("{}{} => {}{}{}", indent, dep.name, path, note, kind);
+		for child in library.needs.iter() {
+			self.print_node(child, depth + 1);
+		}
+	}
+}
+
+pub fn print_tree(executable: &Object, deps: &HashMap<String, Library>, settings: &Settings) {
+	println!(
+		"{} ({})",
+		settings.executable_name(),
+		settings.executable.to_string_lossy()
+	);
+
+	let mut printer = TreePrinter {
+		deps,
+		expanded: HashSet::new(),
+	};
+	for dep in executable.deps.iter() {
+		printer.print_node(dep, 1);
+	}
+}
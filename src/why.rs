@@ -0,0 +1,217 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::{HashMap, HashSet};
+
+use crate::parser::{Dependency, DependencyKind, Object};
+use crate::resolver::{Library, Status};
+use crate::settings::Settings;
+
+// The number of paths can grow exponentially with the size of the graph.
+const MAX_PATHS: usize = 100;
+
+struct PathFinder<'a> {
+	deps: &'a HashMap<String, Library>,
+	target: &'a str,
+	// Libraries from which the target can be reached. Others are not worth exploring.
+	reaching: HashSet<&'a str>,
+	current: Vec<&'a Dependency>,
+	found: Vec<Vec<&'a Dependency>>,
+	truncated: bool,
+}
+
+impl<'a> PathFinder<'a> {
+	fn visit(&mut self, dep: &'a Dependency) {
+		if self.found.len() >= MAX_PATHS {
+			self.truncated = true;
+			return;
+		}
+		if !self.reaching.contains(dep.name.as_str()) {
+			return;
+		}
+		// Do not follow dependency cycles.
+		if self.current.iter().any(|hop| hop.name == dep.name) {
+			return;
+		}
+
+		self.current.push(dep);
+		if dep.name == self.target {
+			self.found.push(self.current.clone());
+		} else if let Some(library) = self.deps.get(dep.name.as_str()) {
+			for child in library.needs.iter() {
+				self.visit(child);
+			}
+		}
+		self.current.pop();
+	}
+}
+
+// Walk the graph backwards from the target to find all the libraries leading to it.
+fn find_reaching<'a>(deps: &'a HashMap<String, Library>, target: &'a str) -> HashSet<&'a str> {
+	let mut requesters: HashMap<&str, Vec<&str>> = HashMap::new();
+	for (name, library) in deps.iter() {
+		for child in library.needs.iter() {
+			requesters
+				.entry(child.name.as_str())
+				.or_default()
+				.push(name.as_str());
+		}
+	}
+
+	let mut reaching = HashSet::new();
+	reaching.insert(target);
+	let mut queue = vec![target];
+	while let Some(name) = queue.pop() {
+		for requester in requesters.get(name).into_iter().flatten() {
+			if reaching.insert(*requester) {
+				queue.push(*requester);
+			}
+		}
+	}
+	return reaching;
+}
+
+fn describe_hop(dep: &Dependency, library: &Library) -> String {
+	let mut result = match &library.status {
+		Status::Ignored(reason) => format!("ignored, {}", reason.describe()),
+		Status::FailedToResolve => String::from("failed to resolve"),
+		Status::Resolved(path, tier) => {
+			format!("{}, found via {}", path.to_string_lossy(), tier.describe())
+		}
+		Status::LinkerScript(path, tier) => format!(
+			"linker script {}, found via {}",
+			path.to_string_lossy(),
+			tier.describe()
+		),
+		Status::ApiSet(host) => format!("API set, implemented by {}", host),
+		Status::Unreadable(path, tier, err) => format!(
+			"{}, found via {}, but unreadable: {}",
+			path.to_string_lossy(),
+			tier.describe(),
+			err
+		),
+	};
+	if library.overridden {
+		result.push_str(", kept due to --override");
+	}
+	match dep.kind {
+		DependencyKind::Normal => {}
+		DependencyKind::DelayLoad => result.push_str(", delay-load"),
+		DependencyKind::Weak => result.push_str(", weak"),
+	}
+	return result;
+}
+
+// Print every path leading from the executable to the given library.
+// Returns false if the library is not part of the dependency graph.
+pub fn print_why(
+	target: &str,
+	executable: &Object,
+	deps: &HashMap<String, Library>,
+	settings: &Settings,
+) -> bool {
+	// Windows treats DLL names as case-insensitive.
+	let target = match deps.keys().find(|name| {
+		*name == target || (executable.type_.is_exe() && name.eq_ignore_ascii_case(target))
+	}) {
+		Some(name) => name.as_str(),
+		None => return false,
+	};
+
+	let mut finder = PathFinder {
+		deps,
+		target,
+		reaching: find_reaching(deps, target),
+		current: Vec::new(),
+		found: Vec::new(),
+		truncated: false,
+	};
+	for dep in executable.deps.iter() {
+		finder.visit(dep);
+	}
+
+	let exe_name = settings.executable_name();
+	for (index, path) in finder.found.iter().enumerate() {
+		if index > 0 {
+			println!();
+		}
+		println!("{}", exe_name);
+		for (depth, hop) in path.iter().enumerate() {
+			let library = deps.get(hop.name.as_str()).unwrap();
+			println!(
+				"{}-> {} ({})",
+				"   ".repeat(depth),
+				hop.name,
+				describe_hop(hop, library)
+			);
+		}
+	}
+	if finder.truncated {
+		println!();
+		println!("(only the first {} paths are shown)", MAX_PATHS);
+	}
+	return true;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn library(needs: &[String]) -> Library {
+		return Library {
+			status: Status::FailedToResolve,
+			kind: DependencyKind::Normal,
+			requested_by: vec![],
+			needs: needs.iter().map(|name| Dependency::normal(name)).collect(),
+			overridden: false,
+		};
+	}
+
+	#[test]
+	fn skip_branches_not_leading_to_target() {
+		// A ladder of diamonds, which has 2^40 paths through it, none of them leading to the target.
+		let mut deps = HashMap::new();
+		let level = |index: usize| vec![format!("liba{}.so", index), format!("libb{}.so", index)];
+		for index in 1..40 {
+			for name in level(index) {
+				deps.insert(name, library(&level(index + 1)));
+			}
+		}
+		let mut root_needs = level(1);
+		root_needs.push(String::from("libtarget.so"));
+		deps.insert(String::from("libroot.so"), library(&root_needs));
+		deps.insert(String::from("libtarget.so"), library(&[]));
+
+		let root = Dependency::normal("libroot.so");
+		let mut finder = PathFinder {
+			deps: &deps,
+			target: "libtarget.so",
+			reaching: find_reaching(&deps, "libtarget.so"),
+			current: Vec::new(),
+			found: Vec::new(),
+			truncated: false,
+		};
+		finder.visit(&root);
+
+		assert_eq!(finder.found.len(), 1);
+		let names: Vec<&str> = finder.found[0]
+			.iter()
+			.map(|dep| dep.name.as_str())
+			.collect();
+		assert_eq!(names, ["libroot.so", "libtarget.so"]);
+		assert!(!finder.truncated);
+	}
+}
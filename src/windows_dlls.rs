@@ -0,0 +1,193 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use crate::windows_version::WindowsVersion;
+use crate::windows_version::WindowsVersion::*;
+
+// DLLs shipped as part of Windows, which should never be bundled.
+// Each entry lists the lowercase file name, the first version of Windows to include it,
+// and the first one not to include it anymore (if any). Kept in alphabetical order.
+const SYSTEM_DLLS: &[(&str, WindowsVersion, Option<WindowsVersion>)] = &[
+	("advapi32.dll", Xp, None),
+	("avicap32.dll", Xp, None),
+	("avifil32.dll", Xp, None),
+	("avrt.dll", Vista, None),
+	("bcrypt.dll", Vista, None),
+	("bcryptprimitives.dll", Win7, None),
+	("cfgmgr32.dll", Xp, None),
+	("combase.dll", Win8, None),
+	("comctl32.dll", Xp, None),
+	("comdlg32.dll", Xp, None),
+	("credui.dll", Xp, None),
+	("crypt32.dll", Xp, None),
+	("d2d1.dll", Win7, None),
+	("d3d10.dll", Vista, None),
+	("d3d10_1.dll", Vista, None),
+	("d3d11.dll", Win7, None),
+	("d3d12.dll", Win10, None),
+	("d3d9.dll", Xp, None),
+	("d3dcompiler_47.dll", Win81, None),
+	("dbghelp.dll", Xp, None),
+	("dcomp.dll", Win8, None),
+	("ddraw.dll", Xp, None),
+	("dinput8.dll", Xp, None),
+	("dnsapi.dll", Xp, None),
+	("dsound.dll", Xp, None),
+	("dwmapi.dll", Vista, None),
+	("dwrite.dll", Win7, None),
+	("dxgi.dll", Vista, None),
+	("dxva2.dll", Vista, None),
+	("evr.dll", Vista, None),
+	("gdi32.dll", Xp, None),
+	("gdiplus.dll", Xp, None),
+	("glu32.dll", Xp, None),
+	("hid.dll", Xp, None),
+	("imagehlp.dll", Xp, None),
+	("imm32.dll", Xp, None),
+	("iphlpapi.dll", Xp, None),
+	("kernel32.dll", Xp, None),
+	("kernelbase.dll", Win7, None),
+	("ksuser.dll", Xp, None),
+	("ktmw32.dll", Vista, None),
+	("mf.dll", Vista, None),
+	("mfplat.dll", Vista, None),
+	("mfreadwrite.dll", Win7, None),
+	("mpr.dll", Xp, None),
+	("msacm32.dll", Xp, None),
+	("msimg32.dll", Xp, None),
+	("msvcp_win.dll", Win10, None),
+	("msvcrt.dll", Xp, None),
+	("msvfw32.dll", Xp, None),
+	("mswsock.dll", Xp, None),
+	("ncrypt.dll", Vista, None),
+	("netapi32.dll", Xp, None),
+	("normaliz.dll", Vista, None),
+	("ntdll.dll", Xp, None),
+	("odbc32.dll", Xp, None),
+	("ole32.dll", Xp, None),
+	("oleaut32.dll", Xp, None),
+	("oledlg.dll", Xp, None),
+	("opengl32.dll", Xp, None),
+	("powrprof.dll", Xp, None),
+	("propsys.dll", Vista, None),
+	("psapi.dll", Xp, None),
+	("rpcrt4.dll", Xp, None),
+	("sechost.dll", Win7, None),
+	("secur32.dll", Xp, None),
+	("setupapi.dll", Xp, None),
+	("shcore.dll", Win81, None),
+	("shell32.dll", Xp, None),
+	("shfolder.dll", Xp, None),
+	("shlwapi.dll", Xp, None),
+	// Dropped in Vista, but ignored on every target, as it always has been.
+	("shscrap.dll", Xp, None),
+	("ucrtbase.dll", Win10, None),
+	("urlmon.dll", Xp, None),
+	("user32.dll", Xp, None),
+	("userenv.dll", Xp, None),
+	("usp10.dll", Xp, None),
+	("uxtheme.dll", Xp, None),
+	("version.dll", Xp, None),
+	("wevtapi.dll", Vista, None),
+	("windowscodecs.dll", Vista, None),
+	("winhttp.dll", Xp, None),
+	("wininet.dll", Xp, None),
+	("winmm.dll", Xp, None),
+	("winscard.dll", Xp, None),
+	("winspool.drv", Xp, None),
+	("wintrust.dll", Xp, None),
+	("wldap32.dll", Xp, None),
+	("ws2_32.dll", Xp, None),
+	("wsock32.dll", Xp, None),
+	("wtsapi32.dll", Xp, None),
+	("xinput1_4.dll", Win8, None),
+	("xinput9_1_0.dll", Vista, None),
+];
+
+// DLLs listed under the KnownDLLs registry key, which Windows always loads
+// from System32, no matter what the application directory contains.
+// Same layout as the table above.
+const KNOWN_DLLS: &[(&str, WindowsVersion, Option<WindowsVersion>)] = &[
+	("advapi32.dll", Xp, None),
+	("combase.dll", Win8, None),
+	("comdlg32.dll", Xp, None),
+	("gdi32.dll", Xp, None),
+	("gdiplus.dll", Win10, None),
+	("imagehlp.dll", Xp, None),
+	("imm32.dll", Win7, None),
+	("kernel32.dll", Xp, None),
+	("msvcrt.dll", Win7, None),
+	("normaliz.dll", Win7, None),
+	("ole32.dll", Xp, None),
+	("oleaut32.dll", Xp, None),
+	("psapi.dll", Win7, None),
+	("rpcrt4.dll", Xp, None),
+	("sechost.dll", Win7, None),
+	("setupapi.dll", Win7, None),
+	("shcore.dll", Win10, None),
+	("shell32.dll", Xp, None),
+	("shlwapi.dll", Win7, None),
+	("urlmon.dll", Xp, Some(Win8)),
+	("user32.dll", Xp, None),
+	("usp10.dll", Win7, Some(Win8)),
+	("version.dll", Xp, Some(Vista)),
+	("wininet.dll", Xp, Some(Win8)),
+	("wldap32.dll", Xp, None),
+	("ws2_32.dll", Win7, None),
+];
+
+fn is_listed(
+	table: &[(&str, WindowsVersion, Option<WindowsVersion>)],
+	name: &str,
+	target: WindowsVersion,
+) -> bool {
+	let name = name.to_ascii_lowercase();
+	match table.binary_search_by(|(entry, _, _)| entry.cmp(&name.as_str())) {
+		Ok(index) => {
+			let (_, since, until) = table[index];
+			return since <= target && until.map(|until| target < until).unwrap_or(true);
+		}
+		Err(_) => return false,
+	}
+}
+
+// Check if the given DLL ships with the target version of Windows.
+pub fn is_system_dll(name: &str, target: WindowsVersion) -> bool {
+	return is_listed(SYSTEM_DLLS, name, target);
+}
+
+// Check if the given DLL is one of the KnownDLLs on the target version of Windows.
+pub fn is_known_dll(name: &str, target: WindowsVersion) -> bool {
+	return is_listed(KNOWN_DLLS, name, target);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// is_listed() relies on binary search, with names looked up in lowercase.
+	#[test]
+	fn tables_are_sorted() {
+		for table in [SYSTEM_DLLS, KNOWN_DLLS] {
+			for pair in table.windows(2) {
+				assert!(pair[0].0 < pair[1].0, "{} >= {}", pair[0].0, pair[1].0);
+			}
+			for (name, _, _) in table.iter() {
+				assert_eq!(*name, name.to_ascii_lowercase());
+			}
+		}
+	}
+}
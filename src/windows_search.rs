@@ -0,0 +1,296 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+extern crate regex;
+use regex::Regex;
+
+extern crate same_file;
+use same_file::is_same_file;
+
+use crate::parser::{read_embedded_manifest, Object};
+use crate::resolver::{
+	find_in_directory, find_in_toolchain, IgnoreReason, Library, SearchTier, Status,
+};
+use crate::settings::Settings;
+use crate::version::*;
+use crate::windows_dlls::is_known_dll;
+
+// Ways in which Windows can load a DLL from somewhere else than the application directory,
+// before falling back to System32 and PATH.
+#[derive(Default)]
+pub struct Redirection {
+	// The "EXECUTABLE.local" directory, if present and in effect.
+	pub dot_local: Option<PathBuf>,
+	// Private side-by-side assemblies listed in the application manifest.
+	// Maps the (lowercase) names of the DLLs they contain to the assembly directories.
+	pub assemblies: HashMap<String, PathBuf>,
+}
+
+impl Redirection {
+	// The directories Windows would look in before the application directory, in order.
+	pub fn dirs_for(&self, name: &str) -> Vec<(&PathBuf, SearchTier)> {
+		let mut result = Vec::new();
+		if let Some(dir) = &self.dot_local {
+			result.push((dir, SearchTier::DotLocal));
+		}
+		if let Some(dir) = self.assemblies.get(&name.to_ascii_lowercase()) {
+			result.push((dir, SearchTier::Assembly));
+		}
+		return result;
+	}
+}
+
+lazy_static! {
+	static ref DEPENDENT_ASSEMBLY: Regex = Regex::new(
+		r#"(?s)<(?:\w+:)?dependentAssembly\b.*?<(?:\w+:)?assemblyIdentity\b[^>]*?\bname\s*=\s*["']([^"']+)["']"#
+	)
+	.unwrap();
+	static ref ASSEMBLY_FILE: Regex =
+		Regex::new(r#"<(?:\w+:)?file\b[^>]*?\bname\s*=\s*["']([^"']+)["']"#).unwrap();
+}
+
+// Names of the assemblies listed as dependencies in a manifest,
+// except for those shipped with the OS (e.g. Common Controls v6), which live in WinSxS.
+fn dependent_assemblies(manifest: &str) -> Vec<&str> {
+	return DEPENDENT_ASSEMBLY
+		.captures_iter(manifest)
+		.map(|capture| capture.get(1).unwrap().as_str())
+		.filter(|name| !name.starts_with("Microsoft.Windows."))
+		.collect();
+}
+
+// Names of the DLLs making up an assembly, in lowercase.
+fn assembly_files(manifest: &str) -> Vec<String> {
+	return ASSEMBLY_FILE
+		.captures_iter(manifest)
+		.map(|capture| capture[1].to_ascii_lowercase())
+		.collect();
+}
+
+// Embedded manifests take precedence over the ones stored alongside the file.
+fn read_manifest(path: &Path) -> Option<String> {
+	let mut external = path.as_os_str().to_owned();
+	external.push(".manifest");
+	return read_embedded_manifest(path).or_else(|| fs::read_to_string(external).ok());
+}
+
+// Probe the application directory for a private assembly, the way the Windows loader does:
+// NAME.dll, NAME.manifest, NAME/NAME.dll and NAME/NAME.manifest.
+// Returns the directory of the assembly, along with its manifest.
+fn find_private_assembly(app_dir: &Path, name: &str) -> Option<(PathBuf, String)> {
+	for dir in [app_dir.to_path_buf(), app_dir.join(name)] {
+		let dll = dir.join(format!("{}.dll", name));
+		let manifest = match dll.is_file() {
+			true => read_embedded_manifest(&dll),
+			false => None,
+		};
+		let manifest =
+			manifest.or_else(|| fs::read_to_string(dir.join(format!("{}.manifest", name))).ok());
+		if let Some(manifest) = manifest {
+			return Some((dir, manifest));
+		}
+	}
+	return None;
+}
+
+// Check the executable for .local redirection and private assemblies listed in its manifest.
+pub fn load_redirection(settings: &Settings) -> Redirection {
+	let mut result = Redirection::default();
+	let app_dir = settings
+		.executable
+		.parent()
+		.unwrap_or_else(|| Path::new("/"));
+	let manifest = read_manifest(&settings.executable);
+
+	let mut dot_local = settings.executable.as_os_str().to_owned();
+	dot_local.push(".local");
+	let dot_local = PathBuf::from(dot_local);
+	if dot_local.is_dir() {
+		match manifest {
+			// DLL redirection is disabled for applications that have a manifest.
+			Some(_) => {
+				if settings.verbose {
+					println!(
+						"\"{}\": ignored, as the executable has a manifest",
+						dot_local.to_string_lossy()
+					);
+				}
+			}
+			None => result.dot_local = Some(dot_local),
+		}
+	}
+
+	let manifest = match manifest {
+		Some(manifest) => manifest,
+		None => return result,
+	};
+	for name in dependent_assemblies(&manifest) {
+		match find_private_assembly(app_dir, name) {
+			Some((dir, assembly_manifest)) => {
+				for file in assembly_files(&assembly_manifest) {
+					result.assemblies.entry(file).or_insert_with(|| dir.clone());
+				}
+			}
+			None => {
+				if settings.verbose {
+					println!(
+						"\"{}\": assembly not found in the executable's directory",
+						name
+					);
+				}
+			}
+		}
+	}
+	return result;
+}
+
+// The directory the executable will be run from.
+fn app_dir(settings: &Settings) -> PathBuf {
+	match &settings.layout.bin_dir {
+		Some(bin_dir) => return settings.target_dir.join(bin_dir),
+		None => {
+			return settings
+				.executable
+				.parent()
+				.unwrap_or_else(|| Path::new("/"))
+				.to_path_buf()
+		}
+	}
+}
+
+// Leftovers from an earlier run are fine, as long as they're still identical.
+fn same_contents(first: &Path, second: &Path) -> bool {
+	if is_same_file(first, second).unwrap_or(false) {
+		return true;
+	}
+	match (fs::read(first), fs::read(second)) {
+		(Ok(first), Ok(second)) => return first == second,
+		_ => return false,
+	}
+}
+
+// Compare the resolved libraries with what Windows would actually load at runtime,
+// warning about any differences. KnownDLLs are always loaded from System32,
+// while DLLs already present in the application directory win over everything else.
+// DLLs picked up from the application directory are also compared against the toolchain's.
+pub fn check_load_order(executable: &Object, deps: &HashMap<String, Library>, settings: &Settings) {
+	let type_ = &executable.type_;
+	let app_dir = app_dir(settings);
+	// Copies made into the application directory replace whatever was there.
+	let replaced =
+		!settings.no_clobber && is_same_file(&app_dir, settings.lib_target_dir()).unwrap_or(false);
+
+	let mut names: Vec<&String> = deps.keys().collect();
+	names.sort();
+	for name in names {
+		let resolved = match &deps[name].status {
+			Status::Resolved(path, tier) | Status::Unreadable(path, tier, _) => Some((path, tier)),
+			Status::Ignored(IgnoreReason::BuiltinList) => None,
+			_ => continue,
+		};
+		let known = is_known_dll(name, settings.windows_target);
+		if let (Some((path, _)), true) = (resolved, known) {
+			eprintln!(
+				"{}: warning: \"{}\" is a KnownDLL on {}, so Windows will load it from System32 instead of \"{}\"",
+				PROGRAM_NAME,
+				name,
+				settings.windows_target.name(),
+				path.to_string_lossy()
+			);
+		}
+		if known {
+			continue;
+		}
+
+		if let Some((path, SearchTier::AppDir)) = resolved {
+			match find_in_toolchain(name, type_, &executable.arch, settings) {
+				Some(other) if !same_contents(path, &other) => eprintln!(
+					"{}: warning: Windows will load \"{}\" from the application directory, which differs from \"{}\"",
+					PROGRAM_NAME,
+					path.to_string_lossy(),
+					other.to_string_lossy()
+				),
+				_ => {}
+			}
+		}
+
+		let local = match find_in_directory(name, type_, &app_dir) {
+			Some(file_name) => app_dir.join(file_name),
+			None => continue,
+		};
+		match resolved {
+			// Redirection takes precedence over the application directory.
+			Some((_, SearchTier::DotLocal | SearchTier::Assembly)) => {}
+			Some((path, _)) => {
+				if !replaced && !same_contents(path, &local) {
+					eprintln!(
+						"{}: warning: Windows will load \"{}\" from the application directory instead of \"{}\"",
+						PROGRAM_NAME,
+						local.to_string_lossy(),
+						path.to_string_lossy()
+					);
+				}
+			}
+			None => eprintln!(
+				"{}: warning: Windows will load \"{}\" from the application directory instead of the system copy",
+				PROGRAM_NAME,
+				local.to_string_lossy()
+			),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn application_manifest() {
+		let manifest = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<asmv1:assembly xmlns:asmv1="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
+  <asmv1:assemblyIdentity type="win32" name="Example.App" version="1.0.0.0"/>
+  <dependency>
+    <dependentAssembly>
+      <assemblyIdentity type="win32" name="Microsoft.Windows.Common-Controls"
+        version="6.0.0.0" processorArchitecture="*" publicKeyToken="6595b64144ccf1df"/>
+    </dependentAssembly>
+  </dependency>
+  <dependency>
+    <asmv1:dependentAssembly>
+      <asmv1:assemblyIdentity version="1.2.0.0" name='Example.Runtime' type="win32"/>
+    </asmv1:dependentAssembly>
+  </dependency>
+</asmv1:assembly>"#;
+		assert_eq!(dependent_assemblies(manifest), ["Example.Runtime"]);
+	}
+
+	#[test]
+	fn assembly_manifest() {
+		let manifest = r#"<assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
+  <assemblyIdentity type="win32" name="Example.Runtime" version="1.2.0.0"/>
+  <file name="ExampleCore.dll"/>
+  <file hash="0123456789abcdef" name="example_gfx.DLL"></file>
+</assembly>"#;
+		assert_eq!(
+			assembly_files(manifest),
+			["examplecore.dll", "example_gfx.dll"]
+		);
+	}
+}
@@ -0,0 +1,55 @@
+/**
+ * This file is part of the copydeps program.
+ * Copyright (C) 2024 suve (a.k.a. Artur Frenszek-Iwicki)
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License,
+ * either version 3 of the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * this program (LICENCE.txt). If not, see <https://www.gnu.org/licenses/>.
+ */
+// Windows releases, oldest first, so that they can be compared.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WindowsVersion {
+	Xp,
+	Vista,
+	Win7,
+	Win8,
+	Win81,
+	Win10,
+	Win11,
+}
+
+impl WindowsVersion {
+	// Parse the value given to --windows-target.
+	pub fn parse(value: &str) -> Option<WindowsVersion> {
+		match value.to_ascii_lowercase().as_str() {
+			"xp" => return Some(WindowsVersion::Xp),
+			"vista" => return Some(WindowsVersion::Vista),
+			"7" => return Some(WindowsVersion::Win7),
+			"8" => return Some(WindowsVersion::Win8),
+			"8.1" => return Some(WindowsVersion::Win81),
+			"10" => return Some(WindowsVersion::Win10),
+			"11" => return Some(WindowsVersion::Win11),
+			_ => return None,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			WindowsVersion::Xp => return "Windows XP",
+			WindowsVersion::Vista => return "Windows Vista",
+			WindowsVersion::Win7 => return "Windows 7",
+			WindowsVersion::Win8 => return "Windows 8",
+			WindowsVersion::Win81 => return "Windows 8.1",
+			WindowsVersion::Win10 => return "Windows 10",
+			WindowsVersion::Win11 => return "Windows 11",
+		}
+	}
+}
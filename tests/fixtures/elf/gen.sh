@@ -0,0 +1,22 @@
+#!/bin/sh
+# Generates the ELF fixtures used by the unit tests.
+# Requires gcc and GNU ld; run from any directory.
+set -e
+cd "$(dirname "$0")"
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+
+printf 'int x_value(void) { return 1; }\n' > "$tmp/x.c"
+printf 'int x_value(void);\nint fixture_value(void) { return x_value() + 1; }\n' > "$tmp/fixture.c"
+
+# Keep the files small: no page-aligned padding between segments, no symbol table.
+flags="-shared -fPIC -nostdlib -s -Wl,-z,max-page-size=0x1000 -Wl,-z,noseparate-code -Wl,--build-id"
+gcc $flags -Wl,-soname,x.so -o "$tmp/x.so" "$tmp/x.c"
+
+# A plain DT_RPATH, not shared with any other string.
+gcc $flags -Wl,-soname,libfixture.so -Wl,--disable-new-dtags -Wl,-rpath,/opt/fixture/lib \
+	-o libfixture.so "$tmp/fixture.c" "$tmp/x.so"
+
+# The linker stores the DT_RPATH "merged.so" as the tail of the DT_SONAME "libmerged.so".
+gcc $flags -Wl,-soname,libmerged.so -Wl,--disable-new-dtags -Wl,-rpath,merged.so \
+	-o libmerged.so "$tmp/fixture.c" "$tmp/x.so"
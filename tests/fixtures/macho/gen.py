@@ -0,0 +1,62 @@
+#!/usr/bin/env python3
+# Generates the Mach-O fixtures used by the unit tests.
+# They only contain headers and load commands, which is all copydeps reads.
+import os, struct
+
+CPU_TYPE_X86_64 = 0x01000007
+CPU_TYPE_ARM64 = 0x0100000c
+MH_DYLIB = 6
+
+LC_LOAD_DYLIB = 0xc
+LC_ID_DYLIB = 0xd
+LC_LOAD_WEAK_DYLIB = 0x80000018
+LC_RPATH = 0x8000001c
+LC_LAZY_LOAD_DYLIB = 0x20
+
+def pad(data):
+	return data + b'\0' * (-len(data) % 8)
+
+def string_cmd(cmd, header, string):
+	body = pad(header + string.encode() + b'\0')
+	return struct.pack('<II', cmd, 8 + len(body)) + body
+
+def dylib_cmd(cmd, name):
+	# name offset, timestamp, current version, compatibility version
+	return string_cmd(cmd, struct.pack('<IIII', 24, 2, 0x10000, 0x10000), name)
+
+def rpath_cmd(path):
+	return string_cmd(LC_RPATH, struct.pack('<I', 12), path)
+
+def macho(cpu, install_name, loads, rpaths=()):
+	cmds = [dylib_cmd(LC_ID_DYLIB, install_name)]
+	cmds += [dylib_cmd(cmd, name) for cmd, name in loads]
+	cmds += [rpath_cmd(path) for path in rpaths]
+	body = b''.join(cmds)
+	header = struct.pack('<IIIIIIII', 0xfeedfacf, cpu, 0, MH_DYLIB, len(cmds), len(body), 0, 0)
+	return header + body
+
+def fat(slices):
+	align = 3
+	offset = 8 + 20 * len(slices)
+	table = b''
+	data = b''
+	for cpu, blob in slices:
+		table += struct.pack('>IIIII', cpu, 0, offset + len(data), len(blob), align)
+		data += pad(blob)
+	return struct.pack('>II', 0xcafebabe, len(slices)) + table + data
+
+def write(name, data):
+	with open(os.path.join(os.path.dirname(os.path.abspath(__file__)), name), 'wb') as f:
+		f.write(data)
+
+write('libthin.dylib', macho(CPU_TYPE_X86_64, '@rpath/libthin.dylib', [
+	(LC_LOAD_DYLIB, '@rpath/libfoo.dylib'),
+	(LC_LOAD_WEAK_DYLIB, '@loader_path/libopt.dylib'),
+	(LC_LAZY_LOAD_DYLIB, '@executable_path/../Frameworks/liblazy.dylib'),
+	(LC_LOAD_DYLIB, '/usr/lib/libSystem.B.dylib'),
+], ['@loader_path/../lib', '@executable_path/../Frameworks']))
+
+write('libfat.dylib', fat([
+	(CPU_TYPE_ARM64, macho(CPU_TYPE_ARM64, '@rpath/libfat.dylib', [(LC_LOAD_DYLIB, '@rpath/libarm.dylib')])),
+	(CPU_TYPE_X86_64, macho(CPU_TYPE_X86_64, '@rpath/libfat.dylib', [(LC_LOAD_DYLIB, '@rpath/libx86.dylib')])),
+]))